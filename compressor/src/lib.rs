@@ -75,7 +75,9 @@ pub fn compress(input: &[u16], output: &mut [u16]) -> usize {
         if value == last_value && run_length < 255 {
             run_length += 1;
         } else {
-            if run_length >= 3 || data_length == 255 {
+            // The data length is a byte, so flush before a run would be added
+            // to data that is already close to full.
+            if run_length >= 3 || data_length as usize + run_length as usize + 1 > 255 {
                 write(ctrl_word(data_length, run_length));
                 for i in 0..(data_length as usize) {
                     write(input[data_start_index + i]);
@@ -102,6 +104,37 @@ pub fn compress(input: &[u16], output: &mut [u16]) -> usize {
     output_index
 }
 
+// Like `compress`, but runs of `skip_value` are stored as skips, which
+// `decompress` leaves untouched in the output. Used for transparency.
+pub fn compress_skipping(input: &[u16], skip_value: u16, output: &mut [u16]) -> usize {
+    output[0] = input.len() as u16;
+    let mut output_index: usize = 1;
+    let mut input_index: usize = 0;
+    while input_index < input.len() {
+        let skip = input[input_index] == skip_value;
+        let end = input[input_index..]
+            .iter()
+            .position(|&v| (v == skip_value) != skip)
+            .map_or(input.len(), |n| input_index + n);
+        if skip {
+            let mut remaining = end - input_index;
+            while remaining > 0 {
+                let n = remaining.min(255);
+                output[output_index] = ctrl_word(0, n as u8);
+                output_index += 1;
+                remaining -= n;
+            }
+        } else {
+            let length = compress(&input[input_index..end], &mut output[output_index..]);
+            // Drop the size written by `compress`.
+            output.copy_within(output_index + 1..output_index + length, output_index);
+            output_index += length - 1;
+        }
+        input_index = end;
+    }
+    output_index
+}
+
 #[cfg(test)]
 #[macro_use]
 extern crate std;
@@ -205,6 +238,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_compress_skipping() {
+        let input = [0, 0, 0xaa, 0xbb, 0, 0xcc, 0xcc, 0xcc, 0xcc];
+        let mut output = [0; 100];
+        let output_length = compress_skipping(&input, 0, &mut output);
+        assert_eq!(
+            &output[0..output_length],
+            [
+                9,
+                ctrl_word(0, 2),
+                ctrl_word(2, 0),
+                0xaa,
+                0xbb,
+                ctrl_word(0, 1),
+                ctrl_word(1, 3),
+                0xcc
+            ]
+        );
+        let mut decompressed = [0x11; 9];
+        decompress(&output[0..output_length], &mut decompressed);
+        assert_eq!(
+            decompressed,
+            [0x11, 0x11, 0xaa, 0xbb, 0x11, 0xcc, 0xcc, 0xcc, 0xcc]
+        );
+    }
+
+    #[test]
+    fn test_compress_skipping_long_skip() {
+        let input = [0; 300];
+        let mut output = [0; 100];
+        let output_length = compress_skipping(&input, 0, &mut output);
+        assert_eq!(
+            &output[0..output_length],
+            [300, ctrl_word(0, 255), ctrl_word(0, 45)]
+        );
+    }
+
+    #[test]
+    fn test_round_trip_near_max_data_length() {
+        for distinct in 250..258 {
+            for run in 1..5 {
+                let mut input = [0u16; 300];
+                for (i, v) in input.iter_mut().enumerate() {
+                    *v = if i < distinct { 0x100 + i as u16 } else { 0xaa };
+                }
+                // The run only gets added to the data when another value
+                // follows it.
+                input[distinct + run] = 0xbb;
+                let input = &input[0..distinct + run + 1];
+                let mut compressed = [0; 700];
+                let mut output = [0; 300];
+                let length = compress(input, &mut compressed);
+                decompress(&compressed[0..length], &mut output);
+                assert_eq!(input, &output[0..input.len()]);
+
+                let length = compress_skipping(input, 0xffff, &mut compressed);
+                let mut output = [0; 300];
+                decompress(&compressed[0..length], &mut output);
+                assert_eq!(input, &output[0..input.len()]);
+            }
+        }
+    }
+
     #[test]
     fn test_random() {
         let mut total_compressed_size = 0;
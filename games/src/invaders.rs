@@ -6,6 +6,7 @@ use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
 use embedded_graphics::text::{Alignment, Text};
 use heapless::Vec;
 use micromath::vector::I16x2;
+use picosystem::collision;
 use picosystem::display::{Display, HEIGHT, WIDTH};
 use picosystem::fps_monitor::FpsMonitor;
 use picosystem::hardware;
@@ -127,7 +128,14 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
 
         for l in lasers.iter_mut() {
             for e in enemies.iter_mut() {
-                if e.intersects(l) {
+                if e.intersects(l)
+                    && collision::collides(
+                        sprite_enemy(),
+                        e.top_left(),
+                        sprite_laser(),
+                        l.top_left(),
+                    )
+                {
                     e.dead = true;
                     l.dead = true;
                     score += 1;
@@ -148,17 +156,14 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
             display.clear(background_color).unwrap();
 
             for l in lasers.iter() {
-                laser_img.translate(l.top_left()).draw(display).unwrap();
+                display.draw_sprite(sprite_laser(), l.top_left());
             }
 
             for e in enemies.iter() {
-                enemy_img.translate(e.top_left()).draw(display).unwrap();
+                display.draw_sprite(sprite_enemy(), e.top_left());
             }
 
-            player_img
-                .translate(player.top_left())
-                .draw(display)
-                .unwrap();
+            display.draw_sprite(sprite_ship(), player.top_left());
 
             particles.draw(display);
 
@@ -1,24 +1,32 @@
 use embedded_graphics::image::Image;
+use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::*;
-use embedded_graphics::primitives::Rectangle;
 use log::info;
+use picosystem::animation::{Animation, Clip, PlayMode, SpriteSheet};
 use picosystem::display::{Display, HEIGHT, WIDTH};
 use picosystem::fps_monitor::FpsMonitor;
 use picosystem::hardware;
 use picosystem::map::{Map, MapTile, INVALID_TILE};
+use picosystem::palette::Palette;
 use picosystem::tile::{self, GenMapTile, TILE_SIZE};
 use picosystem::time;
 use picosystem_macros::{atlas, map, sprite};
 
 atlas!(atlas, "games/src/mathemagic/terrain_atlas.png", 32);
 
-sprite!(protagonist, "games/src/mathemagic/lidia.png", 576);
+sprite!(
+    protagonist,
+    "games/src/mathemagic/lidia.png",
+    576,
+    compressed
+);
 
 const _: &[u8] = include_bytes!("../../assets/slime/slime_monster_spritesheet.png");
 sprite!(
     slime_atlas,
     "games/assets/slime/slime_monster_spritesheet.png",
-    72
+    72,
+    indexed
 );
 
 const _: &[u8] = include_bytes!("map.tmx");
@@ -32,7 +40,68 @@ enum Direction {
     West,
 }
 
-const SLIME_FRAME_LENGTH: i32 = 30;
+const SLIME_FRAME_LENGTH: u32 = 30;
+const SLIME_MOVE_FRAMES: i32 = 4 * SLIME_FRAME_LENGTH as i32;
+
+// One row of 64px frames per direction: standing, then an 8-frame walk.
+static PLAYER_CLIPS: [Clip<'static>; 8] = [
+    Clip::new("stand_north", &[0], &[1], PlayMode::Loop),
+    Clip::new(
+        "walk_north",
+        &[1, 2, 3, 4, 5, 6, 7, 8],
+        &[3],
+        PlayMode::Loop,
+    ),
+    Clip::new("stand_west", &[9], &[1], PlayMode::Loop),
+    Clip::new(
+        "walk_west",
+        &[10, 11, 12, 13, 14, 15, 16, 17],
+        &[3],
+        PlayMode::Loop,
+    ),
+    Clip::new("stand_south", &[18], &[1], PlayMode::Loop),
+    Clip::new(
+        "walk_south",
+        &[19, 20, 21, 22, 23, 24, 25, 26],
+        &[3],
+        PlayMode::Loop,
+    ),
+    Clip::new("stand_east", &[27], &[1], PlayMode::Loop),
+    Clip::new(
+        "walk_east",
+        &[28, 29, 30, 31, 32, 33, 34, 35],
+        &[3],
+        PlayMode::Loop,
+    ),
+];
+
+// One row of 24px frames per direction.
+static SLIME_CLIPS: [Clip<'static>; 4] = [
+    Clip::new(
+        "north",
+        &[0, 1, 2],
+        &[SLIME_FRAME_LENGTH],
+        PlayMode::PingPong,
+    ),
+    Clip::new(
+        "east",
+        &[3, 4, 5],
+        &[SLIME_FRAME_LENGTH],
+        PlayMode::PingPong,
+    ),
+    Clip::new(
+        "south",
+        &[6, 7, 8],
+        &[SLIME_FRAME_LENGTH],
+        PlayMode::PingPong,
+    ),
+    Clip::new(
+        "west",
+        &[9, 10, 11],
+        &[SLIME_FRAME_LENGTH],
+        PlayMode::PingPong,
+    ),
+];
 
 fn generate_map(position: Point) -> GenMapTile {
     let map = worldmap();
@@ -87,6 +156,23 @@ struct Monster {
     direction: Direction,
     velocity: Point,
     move_frames_remaining: i32,
+    animation: Animation<'static>,
+    variant: usize,
+}
+
+const SLIME_VARIANTS: usize = 3;
+
+// The red slime, then green and blue ones made by rotating the color channels
+// of its palette.
+fn slime_palettes() -> [Palette; SLIME_VARIANTS] {
+    let red = Palette::from_colors(slime_atlas().palette);
+    let mut green = red.clone();
+    let mut blue = red.clone();
+    for (i, c) in red.colors.iter().enumerate() {
+        green.colors[i] = Rgb565::new(c.b(), c.r() << 1, c.g() >> 1);
+        blue.colors[i] = Rgb565::new(c.g() >> 1, c.b() << 1, c.r());
+    }
+    [red, green, blue]
 }
 
 fn move_slime(slime: &mut Monster, rng: &mut oorandom::Rand32) {
@@ -98,7 +184,14 @@ fn move_slime(slime: &mut Monster, rng: &mut oorandom::Rand32) {
             2 => Direction::East,
             _ => Direction::West,
         };
-        slime.move_frames_remaining = SLIME_FRAME_LENGTH * 4;
+        slime.move_frames_remaining = SLIME_MOVE_FRAMES;
+        slime.animation.play(match slime.direction {
+            Direction::North => "north",
+            Direction::East => "east",
+            Direction::South => "south",
+            Direction::West => "west",
+        });
+        slime.animation.restart();
         slime.velocity = match slime.direction {
             Direction::North => Point::new(0, -speed),
             Direction::South => Point::new(0, speed),
@@ -107,34 +200,29 @@ fn move_slime(slime: &mut Monster, rng: &mut oorandom::Rand32) {
         };
     }
 
+    // Rest on the first frame, and hop faster while stretched on the last.
     let do_move = matches!(
-        (
-            slime.move_frames_remaining / SLIME_FRAME_LENGTH % 4,
-            slime.move_frames_remaining % 7,
-        ),
-        (1, 0) | (3, 0) | (2, 0) | (2, 3)
+        (slime.animation.frame() % 3, slime.move_frames_remaining % 7),
+        (1, 0) | (2, 0) | (2, 3)
     );
     if do_move {
         slime.position += slime.velocity;
     }
 
+    slime.animation.advance(1);
     slime.move_frames_remaining -= 1;
 }
 
-fn draw_slime(slime: &Monster, display: &mut Display, player_position: Point) {
+fn draw_slime(
+    slime: &Monster,
+    display: &mut Display,
+    player_position: Point,
+    palettes: &[Palette; SLIME_VARIANTS],
+) {
     let s: u32 = 24;
-    let mut anim_frame = slime.move_frames_remaining / SLIME_FRAME_LENGTH % 4;
-    if anim_frame == 3 {
-        anim_frame = 1;
-    }
-    let atlas_y = match slime.direction {
-        Direction::North => 0,
-        Direction::East => s as i32,
-        Direction::South => 2 * s as i32,
-        Direction::West => 3 * s as i32,
-    };
-    let atlas_coord = Point::new(anim_frame * 24, atlas_y);
-    let slime_sprite = slime_atlas().sub_image(&Rectangle::new(atlas_coord, Size::new(s, s)));
+    let slime_atlas = slime_atlas().with_palette(&palettes[slime.variant].colors);
+    let sheet = SpriteSheet::new(&slime_atlas, Size::new(s, s));
+    let slime_sprite = sheet.frame(slime.animation.frame());
     Image::new(&slime_sprite, Point::new(0, 0))
         .translate(slime.position - player_position - Point::new(s as i32, s as i32) / 2)
         .draw(display)
@@ -152,11 +240,12 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
 
     let mut position = Point::new((100 * 32 - 240) / 2, (100 * 32 - 240) / 2);
     let mut frame = 0;
-    let mut walk_frame = 0;
     let mut player_direction = Direction::North;
+    let mut player_animation = Animation::new(&PLAYER_CLIPS);
 
+    let slime_palettes = slime_palettes();
     let mut slimes: heapless::Vec<Monster, 8> = heapless::Vec::new();
-    for _ in 0..8 {
+    for i in 0..8 {
         slimes
             .push(Monster {
                 position: position
@@ -167,32 +256,42 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
                     ),
                 direction: Direction::South,
                 velocity: Point::new(0, 0),
-                move_frames_remaining: rng.rand_range(0..120) as i32,
+                move_frames_remaining: rng.rand_range(0..SLIME_MOVE_FRAMES as u32) as i32,
+                animation: Animation::new(&SLIME_CLIPS),
+                variant: i % SLIME_VARIANTS,
             })
             .unwrap();
     }
 
     loop {
         let speed = 2;
+        let mut walking = true;
         if hw.input.dpad_left.is_held() {
             position.x -= speed;
             player_direction = Direction::West;
-            walk_frame += 1;
         } else if hw.input.dpad_right.is_held() {
             position.x += speed;
             player_direction = Direction::East;
-            walk_frame += 1;
         } else if hw.input.dpad_up.is_held() {
             position.y -= speed;
             player_direction = Direction::North;
-            walk_frame += 1;
         } else if hw.input.dpad_down.is_held() {
             position.y += speed;
             player_direction = Direction::South;
-            walk_frame += 1;
         } else {
-            walk_frame = 0;
+            walking = false;
         }
+        player_animation.play(match (player_direction, walking) {
+            (Direction::North, false) => "stand_north",
+            (Direction::North, true) => "walk_north",
+            (Direction::West, false) => "stand_west",
+            (Direction::West, true) => "walk_west",
+            (Direction::South, false) => "stand_south",
+            (Direction::South, true) => "walk_south",
+            (Direction::East, false) => "stand_east",
+            (Direction::East, true) => "walk_east",
+        });
+        player_animation.advance(1);
 
         for slime in slimes.iter_mut() {
             move_slime(slime, &mut rng);
@@ -202,30 +301,18 @@ pub fn main(hw: &mut hardware::Hardware) -> ! {
 
         hw.draw(|display| {
             let s: u32 = 64;
-            let player_atlas = protagonist();
-            let walk_anim = if walk_frame == 0 {
-                0
-            } else {
-                1 + (walk_frame / 3) % 8
-            };
-            let atlas_coord = match player_direction {
-                Direction::North => Point::new(0, 0),
-                Direction::East => Point::new(0, 3 * s as i32),
-                Direction::South => Point::new(0, 2 * s as i32),
-                Direction::West => Point::new(0, s as i32),
-            } + Point::new(walk_anim * s as i32, 0);
-            let player_sprite =
-                player_atlas.sub_image(&Rectangle::new(atlas_coord, Size::new(s, s)));
-            Image::new(&player_sprite, Point::new(0, 0))
-                .translate(Point::new(
+            let sheet = SpriteSheet::new(protagonist(), Size::new(s, s));
+            display.draw_sprite_area(
+                sheet.sprite,
+                &sheet.frame_rect(player_animation.frame()),
+                Point::new(
                     (WIDTH as i32 - s as i32) / 2,
                     (HEIGHT as i32 - s as i32) / 2,
-                ))
-                .draw(display)
-                .unwrap();
+                ),
+            );
 
             for slime in slimes.iter() {
-                draw_slime(slime, display, position);
+                draw_slime(slime, display, position, &slime_palettes);
             }
         });
 
@@ -0,0 +1,206 @@
+use crate::sprite::Sprite;
+use embedded_graphics::image::{ImageDrawable, SubImage};
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+// A grid of equally sized frames over a sprite, numbered left to right and
+// then top to bottom. Works with any sprite type, such as `IndexedSprite`.
+#[derive(Debug, Clone, Copy)]
+pub struct SpriteSheet<'a, I = Sprite<'a>> {
+    pub sprite: &'a I,
+    pub frame_size: Size,
+}
+
+impl<'a, I: ImageDrawable<Color = Rgb565>> SpriteSheet<'a, I> {
+    pub fn new(sprite: &'a I, frame_size: Size) -> Self {
+        SpriteSheet { sprite, frame_size }
+    }
+
+    pub fn columns(&self) -> u32 {
+        self.sprite.size().width / self.frame_size.width
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.sprite.size().height / self.frame_size.height
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.columns() * self.rows()
+    }
+
+    pub fn frame_rect(&self, index: u32) -> Rectangle {
+        let columns = self.columns();
+        Rectangle::new(
+            Point::new(
+                ((index % columns) * self.frame_size.width) as i32,
+                ((index / columns) * self.frame_size.height) as i32,
+            ),
+            self.frame_size,
+        )
+    }
+
+    pub fn frame(&self, index: u32) -> SubImage<'a, I> {
+        self.sprite.sub_image(&self.frame_rect(index))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayMode {
+    Loop,
+    // Plays forwards then backwards, without repeating the end frames.
+    PingPong,
+    // Stops on the last frame.
+    Once,
+}
+
+// A sequence of sheet frames. Durations are in whatever unit the animation is
+// advanced by, such as frames or microseconds. A single duration applies to
+// every frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Clip<'a> {
+    name: &'a str,
+    frames: &'a [u32],
+    durations: &'a [u32],
+    mode: PlayMode,
+}
+
+impl<'a> Clip<'a> {
+    // Panics unless there is at least one frame, and either one duration or
+    // one per frame. Build clips in a `static` or `const` to check this at
+    // compile time.
+    pub const fn new(
+        name: &'a str,
+        frames: &'a [u32],
+        durations: &'a [u32],
+        mode: PlayMode,
+    ) -> Self {
+        assert!(!frames.is_empty());
+        assert!(durations.len() == 1 || durations.len() == frames.len());
+        Clip {
+            name,
+            frames,
+            durations,
+            mode,
+        }
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn frames(&self) -> &'a [u32] {
+        self.frames
+    }
+
+    pub fn mode(&self) -> PlayMode {
+        self.mode
+    }
+
+    pub fn duration(&self, step: usize) -> u32 {
+        match self.durations {
+            [duration] => *duration,
+            durations => durations[step],
+        }
+    }
+}
+
+// Plays one clip at a time from a set of clips.
+#[derive(Debug, Clone)]
+pub struct Animation<'a> {
+    clips: &'a [Clip<'a>],
+    clip: usize,
+    step: usize,
+    reverse: bool,
+    elapsed: u32,
+    finished: bool,
+}
+
+impl<'a> Animation<'a> {
+    // Starts playing the first clip.
+    pub fn new(clips: &'a [Clip<'a>]) -> Self {
+        assert!(!clips.is_empty());
+        Animation {
+            clips,
+            clip: 0,
+            step: 0,
+            reverse: false,
+            elapsed: 0,
+            finished: false,
+        }
+    }
+
+    // Switches to the named clip from its start, unless it is already
+    // playing. Unknown names are ignored.
+    pub fn play(&mut self, name: &str) {
+        if let Some(clip) = self.clips.iter().position(|c| c.name == name) {
+            if clip != self.clip {
+                self.clip = clip;
+                self.restart();
+            }
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.step = 0;
+        self.reverse = false;
+        self.elapsed = 0;
+        self.finished = false;
+    }
+
+    pub fn clip(&self) -> &Clip<'a> {
+        &self.clips[self.clip]
+    }
+
+    // The sheet frame to draw.
+    pub fn frame(&self) -> u32 {
+        self.clip().frames[self.step]
+    }
+
+    // True once a one-shot clip has reached its last frame.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn advance(&mut self, delta: u32) {
+        if self.finished {
+            return;
+        }
+        self.elapsed += delta;
+        while !self.finished {
+            // Zero durations are treated as 1 so that this terminates.
+            let duration = self.clip().duration(self.step).max(1);
+            if self.elapsed < duration {
+                break;
+            }
+            self.elapsed -= duration;
+            self.next_step();
+        }
+    }
+
+    fn next_step(&mut self) {
+        let clip = self.clips[self.clip];
+        let last = clip.frames.len() - 1;
+        match clip.mode {
+            PlayMode::Loop => self.step = if self.step < last { self.step + 1 } else { 0 },
+            PlayMode::Once if self.step < last => self.step += 1,
+            PlayMode::Once => {
+                self.finished = true;
+                self.elapsed = 0;
+            }
+            PlayMode::PingPong if last == 0 => {}
+            PlayMode::PingPong => {
+                if self.step == last {
+                    self.reverse = true;
+                } else if self.step == 0 {
+                    self.reverse = false;
+                }
+                if self.reverse {
+                    self.step -= 1;
+                } else {
+                    self.step += 1;
+                }
+            }
+        }
+    }
+}
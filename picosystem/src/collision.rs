@@ -0,0 +1,139 @@
+use crate::sprite::{IndexedSprite, Orientation, Sprite};
+use crate::tile::{Tile, TILE_SIZE};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+// Which pixels of an image are solid for collision tests.
+pub trait CollisionMask {
+    fn mask_size(&self) -> Size;
+
+    fn is_opaque(&self, p: Point) -> bool;
+
+    // Opacity of up to 32 pixels of row `y` from column `x`, with the
+    // leftmost in the least significant bit. The area is within the image.
+    fn row_bits(&self, x: u32, y: u32, width: u32) -> u32 {
+        let mut bits = 0;
+        for i in 0..width {
+            if self.is_opaque(Point::new((x + i) as i32, y as i32)) {
+                bits |= 1 << i;
+            }
+        }
+        bits
+    }
+}
+
+// Extracts `width` bits from column `x` of a row of mask words.
+fn extract_bits(row: &[u32], x: u32, width: u32) -> u32 {
+    let word = (x / 32) as usize;
+    let shift = x % 32;
+    let mut bits = row[word] >> shift;
+    if shift != 0 && word + 1 < row.len() {
+        bits |= row[word + 1] << (32 - shift);
+    }
+    if width < 32 {
+        bits &= (1 << width) - 1;
+    }
+    bits
+}
+
+impl CollisionMask for Sprite<'_> {
+    fn mask_size(&self) -> Size {
+        self.size()
+    }
+
+    fn is_opaque(&self, p: Point) -> bool {
+        Sprite::is_opaque(self, p)
+    }
+
+    fn row_bits(&self, x: u32, y: u32, width: u32) -> u32 {
+        match self.mask {
+            Some(mask) if self.orientation == Orientation::IDENTITY => {
+                let words = self.mask_words_per_row();
+                let row = &mask[y as usize * words..(y as usize + 1) * words];
+                extract_bits(row, x, width)
+            }
+            _ => {
+                let mut bits = 0;
+                for i in 0..width {
+                    if Sprite::is_opaque(self, Point::new((x + i) as i32, y as i32)) {
+                        bits |= 1 << i;
+                    }
+                }
+                bits
+            }
+        }
+    }
+}
+
+impl CollisionMask for IndexedSprite<'_> {
+    fn mask_size(&self) -> Size {
+        self.size
+    }
+
+    fn is_opaque(&self, p: Point) -> bool {
+        let index = self.data[(p.x + p.y * self.size.width as i32) as usize];
+        Some(index) != self.transparent_index
+    }
+}
+
+impl CollisionMask for Tile {
+    fn mask_size(&self) -> Size {
+        Size::new(TILE_SIZE as u32, TILE_SIZE as u32)
+    }
+
+    fn is_opaque(&self, p: Point) -> bool {
+        self.mask[p.y as usize] & (1 << p.x) != 0
+    }
+
+    fn row_bits(&self, x: u32, y: u32, width: u32) -> u32 {
+        extract_bits(&self.mask[y as usize..y as usize + 1], x, width)
+    }
+}
+
+// Returns the smallest rectangle holding every pixel where the opaque pixels
+// of `a` drawn at `a_position` and `b` drawn at `b_position` overlap, or None
+// if they don't touch.
+pub fn overlap<A, B>(a: &A, a_position: Point, b: &B, b_position: Point) -> Option<Rectangle>
+where
+    A: CollisionMask + ?Sized,
+    B: CollisionMask + ?Sized,
+{
+    let area = Rectangle::new(a_position, a.mask_size())
+        .intersection(&Rectangle::new(b_position, b.mask_size()));
+    area.bottom_right()?;
+
+    let mut top_left = Point::new(i32::MAX, i32::MAX);
+    let mut bottom_right = Point::new(i32::MIN, i32::MIN);
+    for y in area.rows() {
+        let a_y = (y - a_position.y) as u32;
+        let b_y = (y - b_position.y) as u32;
+        let mut x = area.top_left.x;
+        let end = area.top_left.x + area.size.width as i32;
+        while x < end {
+            let width = ((end - x) as u32).min(32);
+            let bits = a.row_bits((x - a_position.x) as u32, a_y, width)
+                & b.row_bits((x - b_position.x) as u32, b_y, width);
+            if bits != 0 {
+                let first = x + bits.trailing_zeros() as i32;
+                let last = x + 31 - bits.leading_zeros() as i32;
+                top_left = top_left.component_min(Point::new(first, y));
+                bottom_right = bottom_right.component_max(Point::new(last, y));
+            }
+            x += width as i32;
+        }
+    }
+
+    if top_left.x > bottom_right.x {
+        None
+    } else {
+        Some(Rectangle::with_corners(top_left, bottom_right))
+    }
+}
+
+pub fn collides<A, B>(a: &A, a_position: Point, b: &B, b_position: Point) -> bool
+where
+    A: CollisionMask + ?Sized,
+    B: CollisionMask + ?Sized,
+{
+    overlap(a, a_position, b, b_position).is_some()
+}
@@ -1,8 +1,15 @@
 use crate::dma::{self, DmaChannel};
+use crate::palette::{lerp_color, Palette, PaletteIndex, PALETTE_SIZE};
+use crate::postprocess::{CompiledPostProcess, PostProcess};
+pub use crate::sprite::Rotation;
+use crate::sprite::{pixel_alpha, Orientation, Sprite};
 use crate::time;
 use core::convert::TryInto;
+use core::sync::atomic::{AtomicBool, Ordering};
+use display_interface::{DataFormat, WriteOnlyDataCommand};
 use display_interface_spi::SPIInterfaceNoCS;
 use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::image::Image;
 use embedded_graphics::{
     pixelcolor::{raw::RawU16, Rgb565},
     prelude::*,
@@ -13,6 +20,7 @@ use embedded_hal::digital::v2::{InputPin, OutputPin};
 use embedded_hal::spi::MODE_3;
 use embedded_time::rate::*;
 use hal::pac;
+use hal::pac::interrupt;
 use hal::spi::Spi;
 use log::info;
 use rp2040_hal as hal;
@@ -24,21 +32,156 @@ use st7789::{TearingEffect, ST7789};
 pub const WIDTH: usize = 240;
 pub const HEIGHT: usize = 240;
 
-static mut FRAMEBUFFER: [u16; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
+// Narrower fills are faster done by the CPU than by setting up DMA for each row.
+const MIN_DMA_FILL_WIDTH: u32 = 8;
 
-pub fn framebuffer() -> &'static mut [u16; WIDTH * HEIGHT] {
-    unsafe { &mut FRAMEBUFFER }
+// GPIO12 is channel A of PWM slice 6.
+const BACKLIGHT_PWM_SLICE: usize = 6;
+const BACKLIGHT_PWM_TOP: u32 = 0xffff;
+
+// The controller has memory for 320 rows, of which the first 240 are shown.
+const LCD_MEMORY_HEIGHT: usize = 320;
+
+const CMD_CASET: u8 = 0x2a;
+const CMD_RASET: u8 = 0x2b;
+const CMD_RAMWR: u8 = 0x2c;
+const CMD_VSCRDEF: u8 = 0x33;
+const CMD_VSCSAD: u8 = 0x37;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // 240x240, sent to the screen directly by DMA.
+    Hires,
+    // 120x120, each pixel is doubled to 2x2 on the screen.
+    Lores120,
+    // 80x80, each pixel is tripled to 3x3 on the screen.
+    Lores80,
+    // 240x240 with one byte per pixel, looked up in the palette while being
+    // sent to the screen. Draw with `IndexedDisplay`. Only needs half of the
+    // hires framebuffer, see `FRAMEBUFFER_BYTES`.
+    Indexed,
+}
+
+impl Mode {
+    pub fn scale(self) -> usize {
+        match self {
+            Mode::Hires | Mode::Indexed => 1,
+            Mode::Lores120 => 2,
+            Mode::Lores80 => 3,
+        }
+    }
+
+    pub fn width(self) -> usize {
+        WIDTH / self.scale()
+    }
+
+    pub fn height(self) -> usize {
+        HEIGHT / self.scale()
+    }
+
+    // Bytes of framebuffer used by the mode.
+    pub fn framebuffer_bytes(self) -> usize {
+        match self {
+            Mode::Indexed => WIDTH * HEIGHT,
+            _ => self.width() * self.height() * 2,
+        }
+    }
+}
+
+// The framebuffer is sized for hires. Games that don't use it can enable the
+// `indexed-framebuffer` feature, which halves the size, or the
+// `lores-framebuffer` feature, which reserves only an eighth of it. Only the
+// modes that fit can be used.
+#[cfg(not(any(feature = "indexed-framebuffer", feature = "lores-framebuffer")))]
+pub const FRAMEBUFFER_BYTES: usize = WIDTH * HEIGHT * 2;
+#[cfg(all(feature = "indexed-framebuffer", not(feature = "lores-framebuffer")))]
+pub const FRAMEBUFFER_BYTES: usize = WIDTH * HEIGHT;
+#[cfg(feature = "lores-framebuffer")]
+pub const FRAMEBUFFER_BYTES: usize = (WIDTH / 2) * (HEIGHT / 2) * 2;
+
+// The mode the display starts in.
+#[cfg(not(any(feature = "indexed-framebuffer", feature = "lores-framebuffer")))]
+const DEFAULT_MODE: Mode = Mode::Hires;
+#[cfg(all(feature = "indexed-framebuffer", not(feature = "lores-framebuffer")))]
+const DEFAULT_MODE: Mode = Mode::Indexed;
+#[cfg(feature = "lores-framebuffer")]
+const DEFAULT_MODE: Mode = Mode::Lores120;
+
+static mut FRAMEBUFFER: [u16; FRAMEBUFFER_BYTES / 2] = [0; FRAMEBUFFER_BYTES / 2];
+
+#[cfg(feature = "double-buffering")]
+static mut SECOND_FRAMEBUFFER: [u16; FRAMEBUFFER_BYTES / 2] = [0; FRAMEBUFFER_BYTES / 2];
+
+// Index of the buffer that drawing goes to. The other buffer (if any) is the
+// one being displayed.
+static mut BACK_BUFFER_INDEX: usize = 0;
+
+fn buffer(index: usize) -> &'static mut [u16; FRAMEBUFFER_BYTES / 2] {
+    match index {
+        #[cfg(feature = "double-buffering")]
+        1 => unsafe { &mut SECOND_FRAMEBUFFER },
+        _ => unsafe { &mut FRAMEBUFFER },
+    }
 }
 
-pub type RealDisplay =
-    ST7789<SPIInterfaceNoCS<Spi<hal::spi::Enabled, pac::SPI0, 8>, DynPin>, DynPin>;
+// Returns the back buffer, which is the one drawn to by `Display`. In the
+// lores modes only the first `width * height` pixels are used.
+pub fn framebuffer() -> &'static mut [u16] {
+    buffer(unsafe { BACK_BUFFER_INDEX })
+}
+
+// Returns the back buffer as bytes, for use in `Mode::Indexed`. It shares
+// storage with `framebuffer()`.
+pub fn indexed_framebuffer() -> &'static mut [u8] {
+    unsafe {
+        core::slice::from_raw_parts_mut(framebuffer().as_mut_ptr() as *mut u8, FRAMEBUFFER_BYTES)
+    }
+}
+
+// Palette used by the scanout, with colors stored big-endian.
+static mut PALETTE: [u16; PALETTE_SIZE] = [0; PALETTE_SIZE];
+
+// Effects applied by the scanout, as of the last flush.
+static mut POST_PROCESS: PostProcess = PostProcess::new();
+static mut COMPILED_POST_PROCESS: CompiledPostProcess = CompiledPostProcess::new();
+
+static mut FLUSHED_ROWS: u32 = 0;
+
+// Returns the number of rows sent to the screen since the last call.
+pub fn take_flushed_rows() -> u32 {
+    unsafe { core::mem::replace(&mut FLUSHED_ROWS, 0) }
+}
+
+pub type LcdInterface = SPIInterfaceNoCS<Spi<hal::spi::Enabled, pac::SPI0, 8>, DynPin>;
+pub type RealDisplay = ST7789<LcdInterface, DynPin>;
 
 pub struct Display {
-    st7789: RealDisplay,
-    backlight_pin: DynPin,
+    // The ST7789 driver is only used for initialization, after that commands
+    // are sent directly.
+    lcd: LcdInterface,
+    _lcd_reset_pin: DynPin,
+    // Owned so that nothing else reconfigures the backlight PWM pin.
+    _backlight_pin: DynPin,
     lcd_vsync_pin: DynPin,
     dma_channel: DmaChannel,
     last_vsync_time: u32,
+    double_buffering: bool,
+    dirty: Rectangle,
+    // With double buffering, the regions drawn into the front buffer while it
+    // was the back buffer, and the regions drawn into the back buffer since
+    // the last swap.
+    previous_dirty: Rectangle,
+    back_dirty: Rectangle,
+    mode: Mode,
+    palette: [u16; PALETTE_SIZE],
+    rotation: Rotation,
+    mirrored: bool,
+    brightness: u8,
+    scroll_top: usize,
+    scroll_height: usize,
+    scroll_offset: usize,
+    sent_scroll_offset: usize,
+    post_process: PostProcess,
 }
 
 impl Display {
@@ -57,7 +200,17 @@ impl Display {
         dma_channel: DmaChannel,
     ) -> Display {
         info!("Initializing display");
-        backlight_pin.into_push_pull_output();
+        resets.reset.modify(|_, w| w.pwm().clear_bit());
+        while resets.reset_done.read().pwm().bit_is_clear() {}
+        backlight_pin
+            .try_into_mode(DynPinMode::Function(DynFunction::Pwm))
+            .unwrap();
+        unsafe {
+            let pwm = &(*pac::PWM::PTR).ch[BACKLIGHT_PWM_SLICE];
+            pwm.top.write(|w| w.bits(BACKLIGHT_PWM_TOP));
+            pwm.cc.write(|w| w.bits(0));
+            pwm.csr.write(|w| w.en().set_bit());
+        }
         lcd_dc_pin.into_push_pull_output();
         lcd_cs_pin.into_push_pull_output();
         lcd_cs_pin.set_low().unwrap();
@@ -79,61 +232,516 @@ impl Display {
         let mut st7789 = ST7789::new(di, lcd_reset_pin, WIDTH as u16, HEIGHT as u16);
         st7789.init(delay_source).unwrap();
         st7789.set_tearing_effect(TearingEffect::Vertical).unwrap();
-        let mut display = Display {
-            st7789,
-            backlight_pin,
-            dma_channel,
-            lcd_vsync_pin,
-            last_vsync_time: 0,
-        };
         // A single clear occasionally fails to clear the screen.
         for _ in 0..2 {
             let colors =
                 core::iter::repeat(RawU16::from(Rgb565::BLACK).into_inner()).take(WIDTH * HEIGHT);
-            display
-                .st7789
+            st7789
                 .set_pixels(0, 0, (WIDTH - 1) as u16, (HEIGHT - 1) as u16, colors)
                 .unwrap();
         }
+        let (lcd, lcd_reset_pin) = st7789.release();
+        let mut display = Display {
+            lcd,
+            _lcd_reset_pin: lcd_reset_pin,
+            _backlight_pin: backlight_pin,
+            dma_channel,
+            lcd_vsync_pin,
+            last_vsync_time: 0,
+            double_buffering: false,
+            dirty: Rectangle::zero(),
+            previous_dirty: Rectangle::zero(),
+            back_dirty: Rectangle::zero(),
+            mode: DEFAULT_MODE,
+            palette: [0; PALETTE_SIZE],
+            rotation: Rotation::Rotate0,
+            mirrored: false,
+            brightness: 255,
+            scroll_top: 0,
+            scroll_height: HEIGHT,
+            scroll_offset: 0,
+            sent_scroll_offset: 0,
+            post_process: PostProcess::new(),
+        };
+        display.set_scroll_region(0, 0);
         display.enable_backlight();
+        unsafe {
+            pac::NVIC::unmask(pac::Interrupt::DMA_IRQ_0);
+        }
         display
     }
 
+    // Sends the rows covered by the dirty region. The column window always
+    // spans the full width so that the rows can be sent in a single DMA
+    // transfer.
     fn start_flush(&mut self) {
+        if self.scroll_offset != self.sent_scroll_offset {
+            self.wait_for_spi_idle();
+            let start = (self.scroll_top + self.scroll_offset) as u16;
+            self.command_u16(CMD_VSCSAD, &[start]);
+            self.sent_scroll_offset = self.scroll_offset;
+        }
         unsafe {
-            dma::start_copy_to_spi(
-                &mut self.dma_channel,
-                framebuffer().as_ptr() as u32,
-                (*pac::SPI0::PTR).sspdr.as_ptr() as u32,
-                1,
-                (WIDTH * HEIGHT * 2) as u32,
-            );
+            if POST_PROCESS != self.post_process {
+                POST_PROCESS = self.post_process.clone();
+                COMPILED_POST_PROCESS = CompiledPostProcess::compile(&self.post_process);
+                self.mark_all_dirty();
+            }
+        }
+        let mut dirty = core::mem::replace(&mut self.dirty, Rectangle::zero());
+        if self.is_double_buffered() {
+            // What was drawn into the other buffer for the previous frame is
+            // missing from this one, so that region is sent again for the
+            // screen to match the buffer being presented.
+            self.back_dirty = union(&self.back_dirty, &dirty);
+            dirty = union(&dirty, &self.previous_dirty).intersection(&self.bounding_box());
+        }
+        let scale = self.mode.scale() as i32;
+        let (top, bottom) = match dirty.bottom_right() {
+            Some(bottom_right) => {
+                let a = self.to_screen(dirty.top_left * scale);
+                let b =
+                    self.to_screen((bottom_right + Point::new(1, 1)) * scale - Point::new(1, 1));
+                (a.y.min(b.y) as usize, a.y.max(b.y) as usize)
+            }
+            None => return,
+        };
+        self.wait_for_spi_idle();
+        self.set_window(top, bottom);
+        let rows = bottom - top + 1;
+        unsafe {
+            if self.mode == Mode::Hires
+                && self.rotation == Rotation::Rotate0
+                && !self.mirrored
+                && POST_PROCESS.is_empty()
+            {
+                dma::start_copy_to_spi(
+                    &mut self.dma_channel,
+                    framebuffer().as_ptr().add(top * WIDTH) as u32,
+                    (*pac::SPI0::PTR).sspdr.as_ptr() as u32,
+                    1,
+                    (rows * WIDTH * 2) as u32,
+                );
+            } else {
+                PALETTE = self.palette;
+                start_scanout(
+                    &mut self.dma_channel,
+                    self.mode,
+                    self.rotation,
+                    self.mirrored,
+                    top,
+                    bottom,
+                );
+            }
+            FLUSHED_ROWS += rows as u32;
+        }
+    }
+
+    // The SPI peripheral must be idle before sending commands, otherwise the
+    // DC pin would change while pixel data is still being shifted out. The RX
+    // FIFO fills up with garbage during DMA transfers and is drained so that
+    // blocking writes wait for their own bytes.
+    fn wait_for_spi_idle(&mut self) {
+        let spi = unsafe { &*pac::SPI0::PTR };
+        while spi.sspsr.read().bsy().bit_is_set() {}
+        while spi.sspsr.read().rne().bit_is_set() {
+            let _ = spi.sspdr.read();
+        }
+    }
+
+    fn command(&mut self, command: u8, data: &[u8]) {
+        self.lcd.send_commands(DataFormat::U8(&[command])).unwrap();
+        self.lcd.send_data(DataFormat::U8(data)).unwrap();
+    }
+
+    // Sends a command with 16-bit parameters, most significant byte first.
+    fn command_u16(&mut self, command: u8, params: &[u16]) {
+        let mut data = [0u8; 6];
+        for (dst, param) in data.chunks_mut(2).zip(params.iter()) {
+            dst.copy_from_slice(&param.to_be_bytes());
         }
+        self.command(command, &data[..params.len() * 2]);
+    }
+
+    // Selects full-width rows `top..=bottom` and starts a memory write. The DC
+    // pin is left high, so pixel data can follow directly on the SPI bus.
+    fn set_window(&mut self, top: usize, bottom: usize) {
+        self.command_u16(CMD_CASET, &[0, (WIDTH - 1) as u16]);
+        self.command_u16(CMD_RASET, &[top as u16, bottom as u16]);
+        self.command(CMD_RAMWR, &[]);
     }
 
     fn wait_for_flush(&mut self) {
+        while SCANOUT_ACTIVE.load(Ordering::Acquire) {}
         self.dma_channel.wait();
     }
 
     pub fn flush(&mut self) {
+        self.wait_for_flush();
         self.wait_for_vsync();
         self.start_flush();
         self.wait_for_flush();
     }
 
     pub fn draw(&mut self, func: impl FnOnce(&mut Self)) {
-        self.wait_for_flush();
+        if !self.is_double_buffered() {
+            self.wait_for_flush();
+        }
         func(self);
+        self.present();
+    }
+
+    // Starts sending the back buffer to the screen. With double buffering the
+    // buffers are then swapped, so drawing can continue while DMA is running.
+    // The new back buffer contains the frame before last.
+    pub fn present(&mut self) {
+        self.wait_for_flush();
         self.wait_for_vsync();
         self.start_flush();
+        if self.is_double_buffered() {
+            unsafe {
+                BACK_BUFFER_INDEX ^= 1;
+            }
+            self.previous_dirty = core::mem::replace(&mut self.back_dirty, Rectangle::zero());
+        }
+    }
+
+    // Effects applied to pixels as they are sent to the screen, leaving the
+    // framebuffer unchanged. Changing them sends the whole screen again on
+    // the next flush. While any are active every flush goes through the
+    // line-by-line scanout, which is slower than sending hires directly.
+    pub fn post_process(&mut self) -> &mut PostProcess {
+        &mut self.post_process
+    }
+
+    pub(crate) fn post_process_ref(&self) -> &PostProcess {
+        &self.post_process
+    }
+
+    #[cfg(feature = "double-buffering")]
+    pub fn set_double_buffering(&mut self, enabled: bool) {
+        if enabled == self.double_buffering {
+            return;
+        }
+        self.wait_for_flush();
+        if enabled {
+            // Start from the image currently on screen.
+            let front = unsafe { BACK_BUFFER_INDEX };
+            unsafe {
+                dma::copy_mem(
+                    &mut self.dma_channel,
+                    buffer(front).as_ptr() as u32,
+                    buffer(front ^ 1).as_mut_ptr() as u32,
+                    4,
+                    (FRAMEBUFFER_BYTES / 4) as u32,
+                );
+                BACK_BUFFER_INDEX = front ^ 1;
+            }
+        } else {
+            // Keep drawing into the buffer that was last presented.
+            unsafe {
+                BACK_BUFFER_INDEX ^= 1;
+            }
+        }
+        self.previous_dirty = Rectangle::zero();
+        self.back_dirty = Rectangle::zero();
+        self.double_buffering = enabled;
+    }
+
+    pub fn is_double_buffered(&self) -> bool {
+        self.double_buffering
+    }
+
+    // Adds an area to the region that will be sent by the next flush. Drawing
+    // through `DrawTarget` does this automatically; code that writes to
+    // `framebuffer()` directly must call it.
+    pub fn mark_dirty(&mut self, area: &Rectangle) {
+        let area = area.intersection(&self.bounding_box());
+        self.dirty = union(&self.dirty, &area);
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = self.bounding_box();
+    }
+
+    pub fn dirty_region(&self) -> Rectangle {
+        self.dirty
+    }
+
+    // Draws a sprite, copying each opaque run of its mask straight into the
+    // framebuffer, by DMA for longer runs. Sprites with alpha are blended with
+    // the framebuffer. Other sprites without a mask, and oriented or
+    // compressed ones, are drawn as an `Image`.
+    pub fn draw_sprite(&mut self, sprite: &Sprite, position: Point) {
+        self.draw_sprite_area(sprite, &sprite.bounding_box(), position);
+    }
+
+    // Draws the part of a sprite inside `area` at `position`, like an `Image`
+    // of `sprite.sub_image(area)`, for frames of sprite sheets.
+    pub fn draw_sprite_area(&mut self, sprite: &Sprite, area: &Rectangle, position: Point) {
+        let area = area.intersection(&sprite.bounding_box());
+        if let (Some(alpha), None) = (sprite.alpha, sprite.compressed_rows) {
+            self.blend_sprite(sprite, alpha, &area, position);
+            return;
+        }
+        let mask = match sprite.mask {
+            Some(mask) if sprite.orientation == Orientation::IDENTITY => mask,
+            _ => {
+                let _ = Image::new(&sprite.sub_image(&area), position).draw(self);
+                return;
+            }
+        };
+        debug_assert!(self.mode != Mode::Indexed);
+        let dst_area = Rectangle::new(position, area.size).intersection(&self.bounding_box());
+        if dst_area.bottom_right().is_none() {
+            return;
+        }
+        self.mark_dirty(&dst_area);
+
+        // Offset from screen to sprite coordinates.
+        let offset = area.top_left - position;
+        let stride = self.size().width as usize;
+        let width = sprite.size.width as usize;
+        let x0 = (dst_area.top_left.x + offset.x) as usize;
+        let x1 = x0 + dst_area.size.width as usize;
+        let fb = framebuffer();
+        let mut dma_channel = unsafe { DmaChannel::new(dma::CHANNEL_FILL) };
+        for y in dst_area.rows() {
+            let sy = (y + offset.y) as usize;
+            for (start, end) in sprite.opaque_runs(mask, sy) {
+                let start = start.max(x0);
+                let end = end.min(x1);
+                if start >= end {
+                    continue;
+                }
+                let src = &sprite.data[sy * width + start..sy * width + end];
+                let index = (start as i32 - offset.x) as usize + y as usize * stride;
+                let dst = &mut fb[index..index + (end - start)];
+                if ((end - start) as u32) < MIN_DMA_FILL_WIDTH {
+                    for (d, s) in dst.iter_mut().zip(src) {
+                        *d = s.to_be();
+                    }
+                    continue;
+                }
+                dma_channel.wait();
+                unsafe {
+                    dma::start_copy_mem_bswap(
+                        &mut dma_channel,
+                        src.as_ptr() as u32,
+                        dst.as_mut_ptr() as u32,
+                        2,
+                        dst.len() as u32,
+                    );
+                }
+            }
+        }
+        dma_channel.wait();
+    }
+
+    // `area` is in oriented sprite coordinates.
+    fn blend_sprite(&mut self, sprite: &Sprite, alpha: &[u8], area: &Rectangle, position: Point) {
+        debug_assert!(self.mode != Mode::Indexed);
+        let dst_area = Rectangle::new(position, area.size).intersection(&self.bounding_box());
+        if dst_area.bottom_right().is_none() {
+            return;
+        }
+        self.mark_dirty(&dst_area);
+
+        let offset = area.top_left - position;
+        let stride = self.size().width as usize;
+        let width = sprite.size.width as i32;
+        let fb = framebuffer();
+        for p in dst_area.points() {
+            let src = sprite.source_point(p + offset);
+            let index = (src.x + src.y * width) as usize;
+            let a = pixel_alpha(alpha, index);
+            if a == 0 {
+                continue;
+            }
+            let dst = &mut fb[p.x as usize + p.y as usize * stride];
+            let color = if a == 15 {
+                sprite.data[index]
+            } else {
+                let background = RawU16::new(u16::from_be(*dst)).into();
+                let color = RawU16::new(sprite.data[index]).into();
+                RawU16::from(lerp_color(background, color, a * 17)).into_inner()
+            };
+            *dst = color.to_be();
+        }
+    }
+
+    // Changes the logical resolution. The framebuffer contents are not
+    // converted, so the caller should redraw everything.
+    pub fn set_mode(&mut self, mode: Mode) {
+        assert!(
+            mode.framebuffer_bytes() <= FRAMEBUFFER_BYTES,
+            "{:?} doesn't fit in the framebuffer",
+            mode
+        );
+        self.wait_for_flush();
+        self.mode = mode;
+        self.scroll_offset = 0;
+        self.mark_all_dirty();
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // Rotation and mirroring are done while sending the framebuffer to the
+    // screen, so drawing always uses the same coordinates.
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.wait_for_flush();
+        self.rotation = rotation;
+        self.scroll_offset = 0;
+        self.mark_all_dirty();
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    // Mirrors the screen horizontally, after rotation.
+    pub fn set_mirrored(&mut self, mirrored: bool) {
+        self.wait_for_flush();
+        self.mirrored = mirrored;
+        self.scroll_offset = 0;
+        self.mark_all_dirty();
+    }
+
+    pub fn is_mirrored(&self) -> bool {
+        self.mirrored
+    }
+
+    // Sets up hardware vertical scrolling of the rows between `top_fixed` rows
+    // at the top and `bottom_fixed` rows at the bottom, which stay in place.
+    // The scrolled rows are a ring buffer in the framebuffer; draw through
+    // `ScrollDisplay` to use screen coordinates. Only works in `Mode::Hires`
+    // without rotation or mirroring.
+    pub fn set_scroll_region(&mut self, top_fixed: usize, bottom_fixed: usize) {
+        assert!(top_fixed + bottom_fixed < HEIGHT);
+        self.wait_for_flush();
+        self.wait_for_spi_idle();
+        self.scroll_top = top_fixed;
+        self.scroll_height = HEIGHT - top_fixed - bottom_fixed;
+        // The rows of memory below the screen belong to the bottom fixed area.
+        self.command_u16(
+            CMD_VSCRDEF,
+            &[
+                top_fixed as u16,
+                self.scroll_height as u16,
+                (bottom_fixed + LCD_MEMORY_HEIGHT - HEIGHT) as u16,
+            ],
+        );
+        self.scroll_offset = 0;
+        self.sent_scroll_offset = usize::MAX;
+        self.mark_all_dirty();
+    }
+
+    // Returns the rows that scroll, in screen coordinates.
+    pub fn scroll_region(&self) -> Rectangle {
+        Rectangle::new(
+            Point::new(0, self.scroll_top as i32),
+            Size::new(WIDTH as u32, self.scroll_height as u32),
+        )
+    }
+
+    // Sets which framebuffer row of the scroll region is shown at its top. It
+    // takes effect at the next flush.
+    pub fn set_scroll_offset(&mut self, offset: usize) {
+        self.scroll_offset = offset % self.scroll_height;
+    }
+
+    pub fn scroll_offset(&self) -> usize {
+        self.scroll_offset
+    }
+
+    // Moves the contents of the scroll region up by `dy` rows, or down if
+    // negative. Returns the newly exposed rows in screen coordinates, which
+    // still show stale content and need to be drawn.
+    pub fn scroll(&mut self, dy: i32) -> Rectangle {
+        let height = self.scroll_height as i32;
+        let offset = (self.scroll_offset as i32 + dy).rem_euclid(height);
+        self.scroll_offset = offset as usize;
+        let rows = dy.abs().min(height);
+        let top = if dy > 0 { height - rows } else { 0 };
+        Rectangle::new(
+            Point::new(0, self.scroll_top as i32 + top),
+            Size::new(WIDTH as u32, rows as u32),
+        )
+    }
+
+    // Converts a screen row to the framebuffer row it is stored in.
+    pub fn scroll_row(&self, y: usize) -> usize {
+        if (self.scroll_top..self.scroll_top + self.scroll_height).contains(&y) {
+            self.scroll_top + (y - self.scroll_top + self.scroll_offset) % self.scroll_height
+        } else {
+            y
+        }
+    }
+
+    // Converts a point in 240x240 framebuffer coordinates to where it appears on
+    // the screen.
+    fn to_screen(&self, p: Point) -> Point {
+        let n = WIDTH as i32 - 1;
+        let p = match self.rotation {
+            Rotation::Rotate0 => p,
+            Rotation::Rotate90 => Point::new(n - p.y, p.x),
+            Rotation::Rotate180 => Point::new(n - p.x, n - p.y),
+            Rotation::Rotate270 => Point::new(p.y, n - p.x),
+        };
+        if self.mirrored {
+            Point::new(n - p.x, p.y)
+        } else {
+            p
+        }
+    }
+
+    // Sets the palette used in `Mode::Indexed`. Every pixel may change color,
+    // so the whole screen is sent again.
+    pub fn set_palette(&mut self, palette: &Palette) {
+        for (dst, color) in self.palette.iter_mut().zip(palette.colors.iter()) {
+            *dst = RawU16::from(*color).into_inner().to_be();
+        }
+        self.mark_all_dirty();
+    }
+
+    // Returns the palette as sent to the screen, with colors stored big-endian.
+    pub(crate) fn palette_be(&self) -> &[u16; PALETTE_SIZE] {
+        &self.palette
     }
 
     pub fn enable_backlight(&mut self) {
-        self.backlight_pin.set_high().unwrap();
+        set_backlight_level(gamma(self.brightness));
     }
 
     pub fn disable_backlight(&mut self) {
-        self.backlight_pin.set_low().unwrap();
+        set_backlight_level(0);
+    }
+
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        self.enable_backlight();
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    // Changes the brightness gradually, blocking until done.
+    pub fn fade_brightness(&mut self, brightness: u8, duration_us: u32) {
+        let start_time = time::time_us();
+        let start = self.brightness as i32;
+        let delta = brightness as i32 - start;
+        loop {
+            let elapsed = time::time_us() - start_time;
+            if elapsed >= duration_us {
+                break;
+            }
+            let b = start + (delta * (elapsed / 1000) as i32) / (duration_us / 1000).max(1) as i32;
+            set_backlight_level(gamma(b as u8));
+        }
+        self.set_brightness(brightness);
     }
 
     pub fn wait_for_vsync(&mut self) {
@@ -145,11 +753,66 @@ impl Display {
         self.last_vsync_time = time::time_us();
     }
 
+    // Returns the number of pixels of the back buffer that have been sent to
+    // the screen. While the front buffer is being sent the back buffer is
+    // entirely safe to draw to.
     pub fn flush_progress(&self) -> usize {
-        if self.dma_channel.get_count() == 0 {
-            return WIDTH * HEIGHT;
+        let width = self.mode.width();
+        let height = self.mode.height();
+        let start = framebuffer().as_ptr() as usize;
+        if SCANOUT_ACTIVE.load(Ordering::Acquire) {
+            return scanout_progress(start as *const u8).unwrap_or(width * height);
+        }
+        let src = self.dma_channel.get_src() as usize;
+        if self.dma_channel.get_count() == 0 || !(start..start + width * height * 2).contains(&src)
+        {
+            return width * height;
+        }
+        (src - start) / 2
+    }
+
+    // Returns the number of rows at the top of the back buffer that the flush
+    // has finished with. The row being read is not included, as the DMA runs
+    // ahead of the SPI FIFO.
+    pub fn safe_rows(&self) -> usize {
+        let width = self.mode.width();
+        let height = self.mode.height();
+        let progress = self.flush_progress();
+        if progress >= width * height {
+            height
+        } else {
+            (progress + 1).saturating_sub(width) / width
+        }
+    }
+
+    // Calls `func` for each band of `band_height` rows, in order from the top,
+    // as soon as the flush has finished reading them. This allows drawing the
+    // next frame while the previous one is still being sent, and changing
+    // drawing state between bands for raster effects. The band is given in
+    // logical coordinates and drawing should stay within it.
+    pub fn race_the_beam(
+        &mut self,
+        band_height: usize,
+        mut func: impl FnMut(&mut Self, Rectangle),
+    ) {
+        assert!(band_height > 0);
+        let width = self.mode.width() as u32;
+        let height = self.mode.height();
+        let mut y = 0;
+        while y < height {
+            let end = (y + band_height).min(height);
+            while self.safe_rows() < end {}
+            let band = Rectangle::new(Point::new(0, y as i32), Size::new(width, (end - y) as u32));
+            func(self, band);
+            y = end;
         }
-        (self.dma_channel.get_src() as usize - framebuffer().as_ptr() as usize) / 2
+    }
+
+    // Like `draw`, but drawing starts before the previous flush has finished,
+    // using `race_the_beam`.
+    pub fn draw_racing(&mut self, band_height: usize, func: impl FnMut(&mut Self, Rectangle)) {
+        self.race_the_beam(band_height, func);
+        self.present();
     }
 }
 
@@ -161,15 +824,22 @@ impl DrawTarget for Display {
     where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
-        const M: u32 = WIDTH as u32 - 1;
+        debug_assert!(self.mode != Mode::Indexed);
+        let size = self.size();
         let fb = framebuffer();
+        let mut bounds = DirtyBounds::new();
         for Pixel(coord, color) in pixels.into_iter() {
-            if let Ok((x @ 0..=M, y @ 0..=M)) = coord.try_into() {
-                let index: u32 = x + y * WIDTH as u32;
+            if let Ok((x, y)) = coord.try_into() {
+                if x >= size.width || y >= size.height {
+                    continue;
+                }
+                let index: u32 = x + y * size.width;
                 let color = RawU16::from(color).into_inner();
                 fb[index as usize] = color.to_be();
+                bounds.add(coord);
             }
         }
+        bounds.mark(self);
 
         Ok(())
     }
@@ -183,9 +853,13 @@ impl DrawTarget for Display {
             return Ok(());
         }
 
+        debug_assert!(self.mode != Mode::Indexed);
+        self.mark_dirty(&clipped_area);
+
         let skip_top_left = clipped_area.top_left - area.top_left;
         let skip_bottom_right = area.bottom_right().unwrap() - clipped_area.bottom_right().unwrap();
 
+        let width = self.size().width as i32;
         let fb = framebuffer();
         let mut colors = colors.into_iter();
 
@@ -200,7 +874,7 @@ impl DrawTarget for Display {
                 colors.next();
             }
 
-            let mut index = clipped_area.top_left.x + (clipped_area.top_left.y + y) * WIDTH as i32;
+            let mut index = clipped_area.top_left.x + (clipped_area.top_left.y + y) * width;
             for _ in 0..clipped_area.size.width {
                 let color = colors.next().unwrap();
                 let color = RawU16::from(color).into_inner();
@@ -216,17 +890,69 @@ impl DrawTarget for Display {
         Ok(())
     }
 
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        debug_assert!(self.mode != Mode::Indexed);
+        let area = area.intersection(&self.bounding_box());
+        if area.bottom_right().is_none() {
+            return Ok(());
+        }
+        self.mark_dirty(&area);
+
+        let stride = self.size().width as usize;
+        let width = area.size.width as usize;
+        let color = RawU16::from(color).into_inner().to_be();
+        let fb = framebuffer();
+        let mut index = area.top_left.x as usize + area.top_left.y as usize * stride;
+
+        if area.size.width < MIN_DMA_FILL_WIDTH {
+            for _ in 0..area.size.height {
+                fb[index..index + width].fill(color);
+                index += stride;
+            }
+            return Ok(());
+        }
+
+        unsafe {
+            let mut dma_channel = DmaChannel::new(dma::CHANNEL_FILL);
+            if width == stride {
+                dma::set_mem(
+                    &mut dma_channel,
+                    &color as *const u16 as u32,
+                    fb.as_mut_ptr().add(index) as u32,
+                    2,
+                    (width * area.size.height as usize) as u32,
+                );
+            } else {
+                for _ in 0..area.size.height {
+                    dma_channel.wait();
+                    dma::start_set_mem(
+                        &mut dma_channel,
+                        &color as *const u16 as u32,
+                        fb.as_mut_ptr().add(index) as u32,
+                        2,
+                        width as u32,
+                    );
+                    index += stride;
+                }
+                dma_channel.wait();
+            }
+        }
+        Ok(())
+    }
+
     fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        debug_assert!(self.mode != Mode::Indexed);
         let color = RawU16::from(color).into_inner().to_be();
         unsafe {
             dma::set_mem(
-                &mut self.dma_channel,
+                &mut DmaChannel::new(dma::CHANNEL_FILL),
                 &color as *const u16 as u32,
                 framebuffer().as_ptr() as u32,
                 2,
-                (WIDTH * HEIGHT) as u32,
+                (self.mode.width() * self.mode.height()) as u32,
             );
         }
+        self.mark_all_dirty();
         if framebuffer()[0] != color {
             log::info!(
                 "incorrect framebuffer[0], expected {} got {}",
@@ -238,9 +964,119 @@ impl DrawTarget for Display {
     }
 }
 
+fn set_backlight_level(level: u16) {
+    unsafe {
+        let pwm = &(*pac::PWM::PTR).ch[BACKLIGHT_PWM_SLICE];
+        pwm.cc
+            .modify(|r, w| w.bits((r.bits() & 0xffff_0000) | level as u32));
+    }
+}
+
+// Converts a brightness to a PWM level with a gamma of 2, so that equal steps
+// in brightness look roughly equal.
+fn gamma(brightness: u8) -> u16 {
+    let b = brightness as u32;
+    (b * b * BACKLIGHT_PWM_TOP / (255 * 255)) as u16
+}
+
+// Bounding box of the pixels written by a single `draw_iter` call.
+struct DirtyBounds {
+    min: Point,
+    max: Point,
+}
+
+impl DirtyBounds {
+    fn new() -> Self {
+        DirtyBounds {
+            min: Point::new(i32::MAX, i32::MAX),
+            max: Point::new(i32::MIN, i32::MIN),
+        }
+    }
+
+    fn add(&mut self, p: Point) {
+        self.min = self.min.component_min(p);
+        self.max = self.max.component_max(p);
+    }
+
+    fn mark(&self, display: &mut Display) {
+        if self.min.x <= self.max.x {
+            display.mark_dirty(&Rectangle::with_corners(self.min, self.max));
+        }
+    }
+}
+
 impl OriginDimensions for Display {
     fn size(&self) -> Size {
-        Size::new(WIDTH as u32, HEIGHT as u32)
+        Size::new(self.mode.width() as u32, self.mode.height() as u32)
+    }
+}
+
+pub struct IndexedDisplay<'a> {
+    display: &'a mut Display,
+}
+
+impl<'a> IndexedDisplay<'a> {
+    pub fn new(display: &'a mut Display) -> IndexedDisplay {
+        assert_eq!(display.mode(), Mode::Indexed);
+        IndexedDisplay { display }
+    }
+}
+
+impl<'a> DrawTarget for IndexedDisplay<'a> {
+    type Color = PaletteIndex;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        const M: u32 = WIDTH as u32 - 1;
+        let fb = indexed_framebuffer();
+        let mut bounds = DirtyBounds::new();
+        for Pixel(coord, color) in pixels.into_iter() {
+            if let Ok((x @ 0..=M, y @ 0..=M)) = coord.try_into() {
+                let index: u32 = x + y * WIDTH as u32;
+                fb[index as usize] = color.0;
+                bounds.add(coord);
+            }
+        }
+        bounds.mark(self.display);
+
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        if area.bottom_right().is_none() {
+            return Ok(());
+        }
+        let fb = indexed_framebuffer();
+        for y in area.rows() {
+            let start = area.top_left.x as usize + y as usize * WIDTH;
+            fb[start..start + area.size.width as usize].fill(color.0);
+        }
+        self.display.mark_dirty(&area);
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        unsafe {
+            dma::set_mem(
+                &mut DmaChannel::new(dma::CHANNEL_FILL),
+                &color.0 as *const u8 as u32,
+                indexed_framebuffer().as_ptr() as u32,
+                1,
+                (WIDTH * HEIGHT) as u32,
+            );
+        }
+        self.display.mark_all_dirty();
+        Ok(())
+    }
+}
+
+impl<'a> OriginDimensions for IndexedDisplay<'a> {
+    fn size(&self) -> Size {
+        self.display.size()
     }
 }
 
@@ -262,15 +1098,21 @@ impl<'a> DrawTarget for XorDisplay<'a> {
     where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
-        const M: u32 = WIDTH as u32 - 1;
+        let size = self.size();
         let fb = framebuffer();
+        let mut bounds = DirtyBounds::new();
         for Pixel(coord, color) in pixels.into_iter() {
-            if let Ok((x @ 0..=M, y @ 0..=M)) = coord.try_into() {
-                let index: u32 = x + y * WIDTH as u32;
+            if let Ok((x, y)) = coord.try_into() {
+                if x >= size.width || y >= size.height {
+                    continue;
+                }
+                let index: u32 = x + y * size.width;
                 let color = RawU16::from(color).into_inner();
                 fb[index as usize] ^= color.to_be();
+                bounds.add(coord);
             }
         }
+        bounds.mark(self.display);
 
         Ok(())
     }
@@ -281,3 +1123,367 @@ impl<'a> OriginDimensions for XorDisplay<'a> {
         self.display.size()
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    // Mixes the drawn color with the framebuffer, where 0 leaves the
+    // framebuffer unchanged and 255 replaces it.
+    Alpha(u8),
+    Additive,
+    Multiply,
+}
+
+impl BlendMode {
+    pub fn blend(self, dst: Rgb565, src: Rgb565) -> Rgb565 {
+        match self {
+            BlendMode::Alpha(alpha) => lerp_color(dst, src, alpha),
+            BlendMode::Additive => Rgb565::new(
+                (dst.r() + src.r()).min(Rgb565::MAX_R),
+                (dst.g() + src.g()).min(Rgb565::MAX_G),
+                (dst.b() + src.b()).min(Rgb565::MAX_B),
+            ),
+            BlendMode::Multiply => Rgb565::new(
+                (dst.r() as u16 * src.r() as u16 / Rgb565::MAX_R as u16) as u8,
+                (dst.g() as u16 * src.g() as u16 / Rgb565::MAX_G as u16) as u8,
+                (dst.b() as u16 * src.b() as u16 / Rgb565::MAX_B as u16) as u8,
+            ),
+        }
+    }
+}
+
+pub struct BlendDisplay<'a> {
+    display: &'a mut Display,
+    mode: BlendMode,
+}
+
+impl<'a> BlendDisplay<'a> {
+    pub fn new(display: &'a mut Display, mode: BlendMode) -> BlendDisplay {
+        BlendDisplay { display, mode }
+    }
+
+    pub fn set_mode(&mut self, mode: BlendMode) {
+        self.mode = mode;
+    }
+}
+
+impl<'a> DrawTarget for BlendDisplay<'a> {
+    type Color = Rgb565;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let size = self.size();
+        let fb = framebuffer();
+        let mut bounds = DirtyBounds::new();
+        for Pixel(coord, color) in pixels.into_iter() {
+            if let Ok((x, y)) = coord.try_into() {
+                if x >= size.width || y >= size.height {
+                    continue;
+                }
+                let index = (x + y * size.width) as usize;
+                let dst = RawU16::new(u16::from_be(fb[index])).into();
+                let color = RawU16::from(self.mode.blend(dst, color)).into_inner();
+                fb[index] = color.to_be();
+                bounds.add(coord);
+            }
+        }
+        bounds.mark(self.display);
+
+        Ok(())
+    }
+}
+
+impl<'a> OriginDimensions for BlendDisplay<'a> {
+    fn size(&self) -> Size {
+        self.display.size()
+    }
+}
+
+// Draws in screen coordinates while hardware scrolling is in use, storing the
+// rows of the scroll region at their place in the ring buffer.
+pub struct ScrollDisplay<'a> {
+    display: &'a mut Display,
+}
+
+impl<'a> ScrollDisplay<'a> {
+    pub fn new(display: &'a mut Display) -> ScrollDisplay {
+        assert_eq!(display.mode(), Mode::Hires);
+        assert_eq!(display.rotation(), Rotation::Rotate0);
+        assert!(!display.is_mirrored());
+        ScrollDisplay { display }
+    }
+
+    // Returns the number of rows from screen row `y` that are stored
+    // contiguously in the framebuffer.
+    fn contiguous_rows(&self, y: usize) -> usize {
+        let top = self.display.scroll_top;
+        let bottom = top + self.display.scroll_height;
+        if y < top {
+            top - y
+        } else if y < bottom {
+            bottom - self.display.scroll_row(y)
+        } else {
+            HEIGHT - y
+        }
+    }
+}
+
+impl<'a> DrawTarget for ScrollDisplay<'a> {
+    type Color = Rgb565;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        const M: u32 = WIDTH as u32 - 1;
+        let fb = framebuffer();
+        let mut bounds = DirtyBounds::new();
+        for Pixel(coord, color) in pixels.into_iter() {
+            if let Ok((x @ 0..=M, y @ 0..=M)) = coord.try_into() {
+                let row = self.display.scroll_row(y as usize);
+                let color = RawU16::from(color).into_inner();
+                fb[x as usize + row * WIDTH] = color.to_be();
+                bounds.add(Point::new(x as i32, row as i32));
+            }
+        }
+        bounds.mark(self.display);
+
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        let mut y = area.top_left.y as usize;
+        let end = y + area.size.height as usize;
+        while y < end {
+            let rows = self.contiguous_rows(y).min(end - y);
+            let top_left = Point::new(area.top_left.x, self.display.scroll_row(y) as i32);
+            let size = Size::new(area.size.width, rows as u32);
+            self.display
+                .fill_solid(&Rectangle::new(top_left, size), color)?;
+            y += rows;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.display.clear(color)
+    }
+}
+
+impl<'a> OriginDimensions for ScrollDisplay<'a> {
+    fn size(&self) -> Size {
+        self.display.size()
+    }
+}
+
+// Modes that can't be sent to the screen directly are expanded one line at a
+// time into a pair of line buffers. The DMA completion interrupt sends each
+// line and prepares the next one while it is being transferred.
+struct Scanout {
+    mode: Mode,
+    rotation: Rotation,
+    mirrored: bool,
+    src: *const u8,
+    line: usize,
+    end_line: usize,
+    sending: usize,
+    rows: [usize; 2],
+}
+
+const NO_ROW: usize = usize::MAX;
+
+static mut SCANOUT: Scanout = Scanout {
+    mode: Mode::Hires,
+    rotation: Rotation::Rotate0,
+    mirrored: false,
+    src: core::ptr::null(),
+    line: 0,
+    end_line: 0,
+    sending: 0,
+    rows: [NO_ROW; 2],
+};
+
+static SCANOUT_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+static mut LINE_BUFFERS: [[u16; WIDTH]; 2] = [[0; WIDTH]; 2];
+
+impl Scanout {
+    fn row(&self, line: usize) -> usize {
+        line / self.mode.scale()
+    }
+
+    // Returns the framebuffer position of the first pixel shown on a row of
+    // the screen (in framebuffer pixels) and the step to the next pixel.
+    fn row_points(&self, row: usize) -> (Point, Point) {
+        let n = self.mode.width() as i32;
+        let y = row as i32;
+        let (start, step) = match self.rotation {
+            Rotation::Rotate0 => (Point::new(0, y), Point::new(1, 0)),
+            Rotation::Rotate90 => (Point::new(y, n - 1), Point::new(0, -1)),
+            Rotation::Rotate180 => (Point::new(n - 1, n - 1 - y), Point::new(-1, 0)),
+            Rotation::Rotate270 => (Point::new(n - 1 - y, 0), Point::new(0, 1)),
+        };
+        if self.mirrored {
+            (start + step * (n - 1), -step)
+        } else {
+            (start, step)
+        }
+    }
+
+    // Like `row_points`, as framebuffer indices.
+    fn row_indices(&self, row: usize) -> (isize, isize) {
+        let n = self.mode.width() as isize;
+        let (start, step) = self.row_points(row);
+        (
+            start.x as isize + start.y as isize * n,
+            step.x as isize + step.y as isize * n,
+        )
+    }
+
+    fn expand(&mut self, buffer: usize, row: usize) {
+        let width = self.mode.width();
+        let scale = self.mode.scale();
+        let dst = unsafe { &mut LINE_BUFFERS[buffer] };
+        let (start, step) = self.row_indices(row);
+        let post_process = unsafe { &COMPILED_POST_PROCESS };
+        if !post_process.is_empty() {
+            // Effects depend on the framebuffer position, so follow it along.
+            let (mut p, p_step) = self.row_points(row);
+            let mut index = start;
+            for pixels in dst.chunks_exact_mut(scale) {
+                let color = if self.mode == Mode::Indexed {
+                    unsafe { PALETTE[*self.src.offset(index) as usize] }
+                } else {
+                    unsafe { *(self.src as *const u16).offset(index) }
+                };
+                pixels.fill(post_process.apply_be(color, p));
+                index += step;
+                p += p_step;
+            }
+        } else if self.mode == Mode::Indexed {
+            let src = unsafe { core::slice::from_raw_parts(self.src, width * width) };
+            let palette = unsafe { &PALETTE };
+            let mut index = start;
+            for pixel in dst.iter_mut() {
+                *pixel = palette[src[index as usize] as usize];
+                index += step;
+            }
+        } else if step == 1 {
+            let src = unsafe {
+                core::slice::from_raw_parts((self.src as *const u16).add(start as usize), width)
+            };
+            match scale {
+                1 => dst.copy_from_slice(src),
+                _ => {
+                    for (pixels, color) in dst.chunks_exact_mut(scale).zip(src.iter()) {
+                        pixels.fill(*color);
+                    }
+                }
+            }
+        } else {
+            let src = unsafe { core::slice::from_raw_parts(self.src as *const u16, width * width) };
+            let mut index = start;
+            for pixels in dst.chunks_exact_mut(scale) {
+                pixels.fill(src[index as usize]);
+                index += step;
+            }
+        }
+        self.rows[buffer] = row;
+    }
+
+    // Starts sending the current line from whichever buffer holds its row, then
+    // prepares the next row in the other buffer.
+    unsafe fn send_line(&mut self, dma_channel: &mut DmaChannel) {
+        let row = self.row(self.line);
+        if self.rows[self.sending] != row {
+            self.sending ^= 1;
+            if self.rows[self.sending] != row {
+                self.expand(self.sending, row);
+            }
+        }
+        dma::start_copy_to_spi(
+            dma_channel,
+            LINE_BUFFERS[self.sending].as_ptr() as u32,
+            (*pac::SPI0::PTR).sspdr.as_ptr() as u32,
+            1,
+            (WIDTH * 2) as u32,
+        );
+        let next_row = row + 1;
+        if next_row <= self.row(self.end_line) && self.rows[self.sending ^ 1] != next_row {
+            self.expand(self.sending ^ 1, next_row);
+        }
+    }
+}
+
+// Returns the smallest rectangle containing both, ignoring empty ones.
+fn union(a: &Rectangle, b: &Rectangle) -> Rectangle {
+    match (a.bottom_right(), b.bottom_right()) {
+        (Some(a_bottom_right), Some(b_bottom_right)) => Rectangle::with_corners(
+            a.top_left.component_min(b.top_left),
+            a_bottom_right.component_max(b_bottom_right),
+        ),
+        (Some(_), None) => *a,
+        _ => *b,
+    }
+}
+
+unsafe fn start_scanout(
+    dma_channel: &mut DmaChannel,
+    mode: Mode,
+    rotation: Rotation,
+    mirrored: bool,
+    top: usize,
+    bottom: usize,
+) {
+    cortex_m::interrupt::free(|_| {
+        SCANOUT = Scanout {
+            mode,
+            rotation,
+            mirrored,
+            src: framebuffer().as_ptr() as *const u8,
+            line: top,
+            end_line: bottom,
+            sending: 0,
+            rows: [NO_ROW; 2],
+        };
+        SCANOUT_ACTIVE.store(true, Ordering::Release);
+        dma::enable_irq0(dma_channel.channel);
+        SCANOUT.send_line(dma_channel);
+    });
+}
+
+// Returns the number of pixels of `src` that have been read by the scanout, or
+// None if it is reading a different buffer.
+fn scanout_progress(src: *const u8) -> Option<usize> {
+    cortex_m::interrupt::free(|_| unsafe {
+        if SCANOUT.src != src {
+            return None;
+        }
+        if SCANOUT.rotation != Rotation::Rotate0 {
+            // Rows of the framebuffer aren't read in order.
+            return Some(0);
+        }
+        Some(SCANOUT.row(SCANOUT.line) * SCANOUT.mode.width())
+    })
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+unsafe fn DMA_IRQ_0() {
+    dma::acknowledge_irq0(dma::CHANNEL_FRAMEBUFFER);
+    if !SCANOUT_ACTIVE.load(Ordering::Acquire) {
+        return;
+    }
+    SCANOUT.line += 1;
+    if SCANOUT.line > SCANOUT.end_line {
+        dma::disable_irq0(dma::CHANNEL_FRAMEBUFFER);
+        SCANOUT_ACTIVE.store(false, Ordering::Release);
+        return;
+    }
+    let mut dma_channel = DmaChannel::new(dma::CHANNEL_FRAMEBUFFER);
+    SCANOUT.send_line(&mut dma_channel);
+}
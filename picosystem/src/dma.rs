@@ -8,6 +8,7 @@ use rp2040_pac::generic::W;
 pub const CHANNEL_FRAMEBUFFER: usize = 0;
 pub const CHANNEL_TILE0: usize = 1;
 pub const CHANNEL_TILE1: usize = 2;
+pub const CHANNEL_FILL: usize = 3;
 
 pub struct DmaChannel {
     pub channel: usize,
@@ -216,3 +217,19 @@ pub(crate) unsafe fn start_copy_to_spi(
         w
     });
 }
+
+pub unsafe fn enable_irq0(channel: usize) {
+    let dma = &*rp2040_pac::DMA::PTR;
+    dma.ints0.write(|w| w.bits(1 << channel));
+    dma.inte0.modify(|r, w| w.bits(r.bits() | (1 << channel)));
+}
+
+pub unsafe fn disable_irq0(channel: usize) {
+    let dma = &*rp2040_pac::DMA::PTR;
+    dma.inte0.modify(|r, w| w.bits(r.bits() & !(1 << channel)));
+}
+
+pub unsafe fn acknowledge_irq0(channel: usize) {
+    let dma = &*rp2040_pac::DMA::PTR;
+    dma.ints0.write(|w| w.bits(1 << channel));
+}
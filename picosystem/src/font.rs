@@ -0,0 +1,174 @@
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::renderer::{CharacterStyle, TextMetrics, TextRenderer};
+use embedded_graphics::text::Baseline;
+
+#[derive(Debug)]
+pub struct Glyph {
+    pub character: char,
+    pub width: u8,
+    // Bit offset of the glyph in `Font::bitmap`. Each glyph is `width` pixels
+    // by `Font::height` rows, one bit per pixel, most significant bit first.
+    pub offset: u32,
+}
+
+// A proportional 1-bit font, usually generated by the `font!` macro.
+#[derive(Debug)]
+pub struct Font<'a> {
+    pub height: u32,
+    // Rows from the top of a glyph to the alphabetic baseline.
+    pub baseline: u32,
+    // Extra pixels between consecutive glyphs.
+    pub letter_spacing: u32,
+    // Sorted by character.
+    pub glyphs: &'a [Glyph],
+    pub bitmap: &'a [u8],
+}
+
+impl<'a> Font<'a> {
+    // Returns the glyph for `c`, falling back to '?' for missing characters.
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        let find = |c| {
+            self.glyphs
+                .binary_search_by_key(&c, |g| g.character)
+                .ok()
+                .map(|i| &self.glyphs[i])
+        };
+        find(c).or_else(|| find('?'))
+    }
+
+    fn pixel(&self, glyph: &Glyph, p: Point) -> bool {
+        let bit = glyph.offset as usize + p.x as usize + p.y as usize * glyph.width as usize;
+        self.bitmap[bit / 8] & (0x80 >> (bit % 8)) != 0
+    }
+
+    pub fn advance(&self, c: char) -> u32 {
+        self.glyph(c)
+            .map(|g| g.width as u32 + self.letter_spacing)
+            .unwrap_or(0)
+    }
+
+    pub fn text_width(&self, text: &str) -> u32 {
+        text.chars().map(|c| self.advance(c)).sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FontStyle<'a, C> {
+    pub font: &'a Font<'a>,
+    pub text_color: Option<C>,
+    pub background_color: Option<C>,
+}
+
+impl<'a, C: PixelColor> FontStyle<'a, C> {
+    pub fn new(font: &'a Font<'a>, text_color: C) -> Self {
+        FontStyle {
+            font,
+            text_color: Some(text_color),
+            background_color: None,
+        }
+    }
+
+    fn top_left(&self, position: Point, baseline: Baseline) -> Point {
+        let height = self.font.height as i32;
+        let y = match baseline {
+            Baseline::Top => 0,
+            Baseline::Bottom => height - 1,
+            Baseline::Middle => (height - 1) / 2,
+            Baseline::Alphabetic => self.font.baseline as i32,
+        };
+        position - Point::new(0, y)
+    }
+}
+
+impl<'a, C: PixelColor> CharacterStyle for FontStyle<'a, C> {
+    type Color = C;
+
+    fn set_text_color(&mut self, text_color: Option<C>) {
+        self.text_color = text_color;
+    }
+
+    fn set_background_color(&mut self, background_color: Option<C>) {
+        self.background_color = background_color;
+    }
+}
+
+impl<'a, C: PixelColor> TextRenderer for FontStyle<'a, C> {
+    type Color = C;
+
+    fn draw_string<D>(
+        &self,
+        text: &str,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let mut top_left = self.top_left(position, baseline);
+        for c in text.chars() {
+            let glyph = match self.font.glyph(c) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            let advance = glyph.width as u32 + self.font.letter_spacing;
+            if let Some(color) = self.background_color {
+                target.fill_solid(
+                    &Rectangle::new(top_left, Size::new(advance, self.font.height)),
+                    color,
+                )?;
+            }
+            if let Some(color) = self.text_color {
+                let area = Rectangle::new(
+                    Point::zero(),
+                    Size::new(glyph.width as u32, self.font.height),
+                );
+                target.draw_iter(
+                    area.points()
+                        .filter(|p| self.font.pixel(glyph, *p))
+                        .map(|p| Pixel(top_left + p, color)),
+                )?;
+            }
+            top_left.x += advance as i32;
+        }
+        Ok(Point::new(top_left.x, position.y))
+    }
+
+    fn draw_whitespace<D>(
+        &self,
+        width: u32,
+        position: Point,
+        baseline: Baseline,
+        target: &mut D,
+    ) -> Result<Point, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        if let Some(color) = self.background_color {
+            target.fill_solid(
+                &Rectangle::new(
+                    self.top_left(position, baseline),
+                    Size::new(width, self.font.height),
+                ),
+                color,
+            )?;
+        }
+        Ok(position + Point::new(width as i32, 0))
+    }
+
+    fn measure_string(&self, text: &str, position: Point, baseline: Baseline) -> TextMetrics {
+        let width = self.font.text_width(text);
+        TextMetrics {
+            bounding_box: Rectangle::new(
+                self.top_left(position, baseline),
+                Size::new(width, self.font.height),
+            ),
+            next_position: position + Point::new(width as i32, 0),
+        }
+    }
+
+    fn line_height(&self) -> u32 {
+        self.font.height
+    }
+}
@@ -1,4 +1,4 @@
-use crate::time;
+use crate::{display, time};
 use log::info;
 
 pub struct FpsMonitor {
@@ -20,7 +20,11 @@ impl FpsMonitor {
     pub fn update(&mut self) {
         let now = time::time_us();
         if now - self.last_time_us >= Self::FPS_INTERVAL_US {
-            info!("FPS: {}", self.frames);
+            info!(
+                "FPS: {} flushed rows/frame: {}",
+                self.frames,
+                display::take_flushed_rows() / self.frames.max(1)
+            );
             self.last_time_us = now;
             self.frames = 0;
         } else {
@@ -1,5 +1,5 @@
 use crate::display::Display;
-use crate::{audio, dma, idle, input, usb_logger};
+use crate::{audio, dma, idle, input, screenshot, usb_logger};
 use embedded_hal::adc::OneShot;
 use embedded_hal::digital::v2::OutputPin;
 use embedded_time::rate::*;
@@ -28,6 +28,7 @@ pub struct Hardware {
     pub input: input::Input,
     pub audio: audio::Audio,
     pub idle: idle::Idle,
+    screenshot_combo_held: bool,
 }
 
 impl Hardware {
@@ -93,6 +94,10 @@ impl Hardware {
         let battery_pin = pins.gpio26.into_floating_input();
         let adc = hal::adc::Adc::new(pac.ADC, &mut pac.RESETS);
 
+        // `Display::new` waits on DMA channels, so take DMA out of reset first.
+        pac.RESETS.reset.modify(|_, w| w.dma().clear_bit());
+        while pac.RESETS.reset_done.read().dma().bit_is_clear() {}
+
         let display = Display::new(
             /*backlight_pin=*/ pins.gpio12.into(),
             /*lcd_dc_pin=*/ pins.gpio9.into(),
@@ -107,9 +112,6 @@ impl Hardware {
             /*dma_channel=*/ unsafe { dma::DmaChannel::new(dma::CHANNEL_FRAMEBUFFER) },
         );
 
-        pac.RESETS.reset.modify(|_, w| w.dma().clear_bit());
-        while pac.RESETS.reset_done.read().dma().bit_is_clear() {}
-
         let input = input::Input::new(
             pins.gpio22.into(),
             pins.gpio21.into(),
@@ -134,6 +136,7 @@ impl Hardware {
             input,
             audio,
             idle: idle::Idle::new(),
+            screenshot_combo_held: false,
         }
     }
 
@@ -188,8 +191,24 @@ impl Hardware {
     pub fn draw(&mut self, func: impl FnOnce(&mut Display)) {
         if self.idle.check_idle(&mut self.input) {
             self.idle.enter_idle(&mut self.display);
+        } else {
+            self.idle.update_dim(&mut self.display);
         }
-        self.display.draw(func);
+        let take_screenshot = self.check_screenshot_request();
+        self.display.draw(|display| {
+            func(display);
+            if take_screenshot {
+                screenshot::send(display);
+            }
+        });
+    }
+
+    // Screenshots are requested by the host or by pressing X and B together.
+    fn check_screenshot_request(&mut self) -> bool {
+        let combo_held = self.input.button_x.is_held() && self.input.button_b.is_held();
+        let combo_pressed = combo_held && !self.screenshot_combo_held;
+        self.screenshot_combo_held = combo_held;
+        usb_logger::take_screenshot_request() || combo_pressed
     }
 
     pub fn read_battery_raw(&mut self) -> u16 {
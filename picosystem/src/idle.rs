@@ -1,9 +1,14 @@
 use crate::{display, input, interrupts, time};
 
 const IDLE_TIME_US: u64 = 300_000_000;
+const DIM_TIME_US: u64 = 240_000_000;
+const DIM_BRIGHTNESS: u8 = 48;
+const FADE_TIME_US: u32 = 500_000;
 
 pub struct Idle {
     last_active_time: u64,
+    // Brightness to restore when the screen has been dimmed.
+    undimmed_brightness: Option<u8>,
 }
 
 #[allow(clippy::new_without_default)]
@@ -11,6 +16,21 @@ impl Idle {
     pub fn new() -> Idle {
         Idle {
             last_active_time: 0,
+            undimmed_brightness: None,
+        }
+    }
+
+    // Dims the screen some time before going idle, and restores it as soon as
+    // there is input.
+    pub fn update_dim(&mut self, display: &mut display::Display) {
+        let inactive_time = time::time_us64() - self.last_active_time;
+        if inactive_time > DIM_TIME_US {
+            if self.undimmed_brightness.is_none() && display.brightness() > DIM_BRIGHTNESS {
+                self.undimmed_brightness = Some(display.brightness());
+                display.set_brightness(DIM_BRIGHTNESS);
+            }
+        } else if let Some(brightness) = self.undimmed_brightness.take() {
+            display.set_brightness(brightness);
         }
     }
 
@@ -25,7 +45,11 @@ impl Idle {
     }
 
     pub fn enter_idle(&mut self, display: &mut display::Display) {
-        display.disable_backlight();
+        let brightness = self
+            .undimmed_brightness
+            .take()
+            .unwrap_or_else(|| display.brightness());
+        display.fade_brightness(0, FADE_TIME_US);
         unsafe {
             let inputs = 16..24;
             for gpio in inputs.clone() {
@@ -39,7 +63,7 @@ impl Idle {
                 interrupts::disable_gpio_interrupt(gpio, interrupts::GpioEvent::EdgeLow);
             }
         }
-        display.enable_backlight();
+        display.fade_brightness(brightness, FADE_TIME_US);
         self.last_active_time = time::time_us64();
     }
 }
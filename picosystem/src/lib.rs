@@ -1,8 +1,15 @@
 #![no_std]
 
+pub mod animation;
+pub mod collision;
+pub mod font;
 pub mod map;
+pub mod mode7;
+pub mod palette;
+pub mod postprocess;
 pub mod sprite;
 pub mod tile;
+pub mod viewport;
 
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod audio;
@@ -25,6 +32,12 @@ pub mod idle;
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod input;
 
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod multicore;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod textbox;
+
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod time;
 
@@ -39,3 +52,6 @@ pub mod usb_logger;
 
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub mod panic;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub mod screenshot;
@@ -0,0 +1,247 @@
+use micromath::F32Ext;
+
+// Fixed point with 16 fractional bits.
+pub const FIXED_ONE: i32 = 1 << 16;
+
+const HALF_SCREEN: f32 = 120.0;
+
+// Ground farther than this many world pixels from the camera isn't drawn, which
+// keeps the coordinates of perspective rows well within 16.16 fixed point.
+pub const FAR_PLANE: f32 = 4096.0;
+
+// Screen column 0 of a scanline samples the map at `start`, and each pixel to
+// the right moves by `step`. Both are in world pixels, in 16.16 fixed point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanlineTransform {
+    pub start_x: i32,
+    pub start_y: i32,
+    pub step_x: i32,
+    pub step_y: i32,
+}
+
+fn to_fixed(v: f32) -> i32 {
+    (v * FIXED_ONE as f32) as i32
+}
+
+impl ScanlineTransform {
+    // Flat rotation and zoom around `center`, which is shown in the middle of
+    // the screen. An angle of 0 has world up pointing up the screen.
+    pub fn rotate_zoom(center: (f32, f32), angle: f32, zoom: f32, y: i32) -> Self {
+        let (sin, cos) = (angle.sin() / zoom, angle.cos() / zoom);
+        let dy = y as f32 - HALF_SCREEN;
+        ScanlineTransform {
+            start_x: to_fixed(center.0 - HALF_SCREEN * cos - dy * sin),
+            start_y: to_fixed(center.1 - HALF_SCREEN * sin + dy * cos),
+            step_x: to_fixed(cos),
+            step_y: to_fixed(sin),
+        }
+    }
+
+    // Perspective view of the ground plane from `camera`, `height` pixels
+    // above it, facing `angle` radians clockwise from world up. Rows at or above
+    // the `horizon` row show sky, returned as None, as do rows beyond
+    // `FAR_PLANE`.
+    pub fn perspective(
+        camera: (f32, f32),
+        angle: f32,
+        height: f32,
+        horizon: i32,
+        y: i32,
+    ) -> Option<Self> {
+        if y <= horizon {
+            return None;
+        }
+        // 90 degree field of view.
+        let focal = HALF_SCREEN;
+        let distance = height * focal / (y - horizon) as f32;
+        if distance > FAR_PLANE {
+            return None;
+        }
+        let (sin, cos) = (angle.sin(), angle.cos());
+        let scale = distance / focal;
+        let center_x = camera.0 + sin * distance;
+        let center_y = camera.1 - cos * distance;
+        Some(ScanlineTransform {
+            start_x: to_fixed(center_x - cos * scale * focal),
+            start_y: to_fixed(center_y - sin * scale * focal),
+            step_x: to_fixed(cos * scale),
+            step_y: to_fixed(sin * scale),
+        })
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+mod device {
+    use crate::display::{framebuffer, Display, Mode, HEIGHT, WIDTH};
+    use crate::map::NUM_LAYERS;
+    use crate::mode7::*;
+    use crate::tile::{load_tile, tile_id, GenMapTile, LoadedTile, Tile, TileId, TILE_SIZE};
+    use embedded_graphics::pixelcolor::{raw::RawU16, Rgb565};
+    use embedded_graphics::prelude::*;
+
+    struct CachedTile {
+        id: Option<(TileId, bool)>,
+        // Scanline counter value when the tile was last used.
+        last_used: u32,
+        tile: LoadedTile,
+    }
+
+    const EMPTY_CACHED_TILE: CachedTile = CachedTile {
+        id: None,
+        last_used: 0,
+        tile: LoadedTile {
+            data: [0; (TILE_SIZE * TILE_SIZE) as usize],
+            mask: [0; TILE_SIZE as usize],
+        },
+    };
+
+    // Decompressed tiles, kept between frames as the same tiles tend to stay
+    // visible. Each entry takes a little over 2 KiB, so the game decides how
+    // many to keep, typically in a static. It should hold at least the
+    // distinct tiles (counting every layer) that a single scanline shows,
+    // which is up to a dozen map cells for a 240 pixel span at 1:1 zoom,
+    // otherwise tiles are decompressed again for every row.
+    pub struct TileCache<const N: usize> {
+        tiles: [CachedTile; N],
+        row_counter: u32,
+    }
+
+    impl<const N: usize> TileCache<N> {
+        pub const fn new() -> Self {
+            TileCache {
+                tiles: [EMPTY_CACHED_TILE; N],
+                row_counter: 0,
+            }
+        }
+
+        // Returns the slot holding `tile`, loading it into the least recently
+        // used slot not in `pinned` if needed.
+        fn get(&mut self, tile: &Tile, masked: bool, pinned: &[usize]) -> usize {
+            let id = Some((tile_id(tile), masked));
+            let slot = match self.tiles.iter().position(|entry| entry.id == id) {
+                Some(slot) => slot,
+                None => {
+                    let slot = (0..N)
+                        .filter(|slot| !pinned.contains(slot))
+                        .min_by_key(|&slot| self.tiles[slot].last_used)
+                        .unwrap();
+                    load_tile(tile, &mut self.tiles[slot].tile, masked);
+                    self.tiles[slot].id = id;
+                    slot
+                }
+            };
+            self.tiles[slot].last_used = self.row_counter;
+            slot
+        }
+
+        // Returns the slots of the layers of a map cell, updating the slots
+        // in `known` that are unset or have been evicted since.
+        fn get_layers(&mut self, known: &mut CellLayers) -> heapless::Vec<usize, NUM_LAYERS> {
+            let mut slots = heapless::Vec::new();
+            for (i, (tile, slot)) in known.iter_mut().enumerate() {
+                let id = Some((tile_id(tile), i > 0));
+                match self.tiles.get_mut(*slot) {
+                    Some(entry) if entry.id == id => entry.last_used = self.row_counter,
+                    _ => *slot = self.get(tile, i > 0, &slots),
+                }
+                let _ = slots.push(*slot);
+            }
+            slots
+        }
+    }
+
+    impl<const N: usize> Default for TileCache<N> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    const NO_SLOT: usize = usize::MAX;
+
+    // The tiles of a map cell with the cache slots they were last found in.
+    type CellLayers = heapless::Vec<(&'static Tile, usize), NUM_LAYERS>;
+
+    // Map cells remembered from one scanline to the next, so that the map
+    // generator is only called for cells that weren't seen on the row above.
+    // Enough for a 240 pixel span at 1:1 zoom in any direction; rows that
+    // cross more cells look up the rest each time.
+    const ROW_CELLS: usize = 16;
+    type RowCells = heapless::LinearMap<Point, CellLayers, ROW_CELLS>;
+
+    // Draws the map through a per-scanline affine transform, for rotated,
+    // zoomed and perspective views. `transform` is called for each screen row;
+    // rows where it returns None are filled with `background`.
+    pub fn draw<F, T, const N: usize>(
+        display: &mut Display,
+        cache: &mut TileCache<N>,
+        map_generator: &F,
+        transform: T,
+        background: Rgb565,
+    ) where
+        F: Fn(Point) -> GenMapTile,
+        T: Fn(i32) -> Option<ScanlineTransform>,
+    {
+        assert_eq!(display.mode(), Mode::Hires);
+        assert!(N >= NUM_LAYERS);
+        display.mark_all_dirty();
+
+        let background = RawU16::from(background).into_inner().to_be();
+        let fb = framebuffer();
+        let mask = TILE_SIZE - 1;
+        let mut previous_cells = RowCells::new();
+        let mut cells = RowCells::new();
+
+        for y in 0..HEIGHT {
+            let row = &mut fb[y * WIDTH..(y + 1) * WIDTH];
+            let t = match transform(y as i32) {
+                Some(t) => t,
+                None => {
+                    row.fill(background);
+                    continue;
+                }
+            };
+            cache.row_counter = cache.row_counter.wrapping_add(1);
+            core::mem::swap(&mut previous_cells, &mut cells);
+            cells.clear();
+
+            let mut u = t.start_x;
+            let mut v = t.start_y;
+            let mut current = Point::new(i32::MIN, i32::MIN);
+            let mut layers = heapless::Vec::<usize, NUM_LAYERS>::new();
+            for dst in row.iter_mut() {
+                let world = Point::new(u >> 16, v >> 16);
+                u = u.wrapping_add(t.step_x);
+                v = v.wrapping_add(t.step_y);
+
+                let map_coord = Point::new(world.x & !mask, world.y & !mask);
+                if map_coord != current {
+                    current = map_coord;
+                    // A straight span doesn't return to a cell it has left.
+                    let mut known = match previous_cells.remove(&map_coord) {
+                        Some(known) => known,
+                        None => map_generator(map_coord)
+                            .layers
+                            .iter()
+                            .map(|tile| (*tile, NO_SLOT))
+                            .collect(),
+                    };
+                    layers = cache.get_layers(&mut known);
+                    let _ = cells.insert(map_coord, known);
+                }
+
+                let tx = (world.x & mask) as usize;
+                let ty = (world.y & mask) as usize;
+                for (i, &slot) in layers.iter().enumerate().rev() {
+                    let tile = &cache.tiles[slot].tile;
+                    if i == 0 || tile.mask[ty] & (1 << tx) != 0 {
+                        *dst = tile.data[tx + ty * TILE_SIZE as usize];
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub use device::{draw, TileCache};
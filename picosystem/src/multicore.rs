@@ -0,0 +1,229 @@
+use crate::display::Display;
+use core::marker::PhantomData;
+use rp_pico::hal::pac;
+
+// In u64 words, so 32 KiB. The deepest render path is `tile::draw`, which
+// keeps about 22 KiB on the stack: the overlay cache of four `LoadedTile`s
+// (8.5 KiB), up to two more `LoadedTile`s being loaded or moved into the
+// cache, the 4 KiB buffer of `load_tile` and the list of tiles still to be
+// overlaid. The rest is headroom for the render function and the display
+// code it calls. Render functions that need more should keep large buffers
+// in statics.
+const CORE1_STACK_SIZE: usize = 4096;
+
+// Stack for core 1, as u64 to keep it 8-byte aligned.
+static mut CORE1_STACK: [u64; CORE1_STACK_SIZE] = [0; CORE1_STACK_SIZE];
+
+// Messages exchanged with the render loop on core 1.
+const MSG_READY: u32 = 1;
+const MSG_FRAME: u32 = 2;
+const MSG_STOP: u32 = 3;
+const MSG_STOPPED: u32 = 4;
+
+struct Core1Task {
+    run: unsafe fn(&Core1Task),
+    render: *const (),
+    display: *mut Display,
+    state: *const (),
+}
+
+static mut CORE1_TASK: Core1Task = Core1Task {
+    run: park,
+    render: core::ptr::null(),
+    display: core::ptr::null_mut(),
+    state: core::ptr::null(),
+};
+
+fn sio() -> &'static pac::sio::RegisterBlock {
+    unsafe { &*pac::SIO::PTR }
+}
+
+// Returns 0 on core 0 and 1 on core 1.
+pub fn core_id() -> u32 {
+    sio().cpuid.read().bits()
+}
+
+pub fn fifo_write(value: u32) {
+    let sio = sio();
+    while sio.fifo_st.read().rdy().bit_is_clear() {}
+    sio.fifo_wr.write(|w| unsafe { w.bits(value) });
+    // Wake the other core if it is waiting for a message.
+    cortex_m::asm::sev();
+}
+
+pub fn fifo_read() -> u32 {
+    let sio = sio();
+    while sio.fifo_st.read().vld().bit_is_clear() {
+        cortex_m::asm::wfe();
+    }
+    sio.fifo_rd.read().bits()
+}
+
+pub fn fifo_try_read() -> Option<u32> {
+    let sio = sio();
+    if sio.fifo_st.read().vld().bit_is_set() {
+        Some(sio.fifo_rd.read().bits())
+    } else {
+        None
+    }
+}
+
+pub fn fifo_drain() {
+    while fifo_try_read().is_some() {}
+}
+
+// Resets core 1 and starts it running `CORE1_TASK`, using the launch protocol
+// of the bootrom.
+unsafe fn launch_core1() {
+    let psm = &*pac::PSM::PTR;
+    psm.frce_off.modify(|_, w| w.proc1().set_bit());
+    while psm.frce_off.read().proc1().bit_is_clear() {}
+    psm.frce_off.modify(|_, w| w.proc1().clear_bit());
+
+    let vector_table = (*pac::PPB::PTR).vtor.read().bits();
+    let stack_pointer = CORE1_STACK.as_mut_ptr().add(CORE1_STACK_SIZE) as u32;
+    let commands = [
+        0,
+        0,
+        1,
+        vector_table,
+        stack_pointer,
+        core1_entry as usize as u32,
+    ];
+    let mut i = 0;
+    while i < commands.len() {
+        let command = commands[i];
+        if command == 0 {
+            fifo_drain();
+            cortex_m::asm::sev();
+        }
+        fifo_write(command);
+        i = if fifo_read() == command { i + 1 } else { 0 };
+    }
+}
+
+extern "C" fn core1_entry() -> ! {
+    unsafe {
+        let task = &CORE1_TASK;
+        (task.run)(task);
+    }
+    loop {
+        cortex_m::asm::wfe();
+    }
+}
+
+unsafe fn park(_task: &Core1Task) {}
+
+unsafe fn render_loop<S>(task: &Core1Task) {
+    let render: fn(&mut Display, &S) = core::mem::transmute(task.render);
+    let display = &mut *task.display;
+    let state = &*(task.state as *const S);
+    // The scanout is driven from the DMA interrupt, which has to run on the
+    // core that owns the display.
+    pac::NVIC::unmask(pac::Interrupt::DMA_IRQ_0);
+    fifo_write(MSG_READY);
+    loop {
+        match fifo_read() {
+            MSG_FRAME => {
+                // Make core 0's writes to the state visible before reading it.
+                cortex_m::asm::dmb();
+                display.draw(|display| render(display, state));
+                cortex_m::asm::dmb();
+                fifo_write(MSG_READY);
+            }
+            MSG_STOP => {
+                pac::NVIC::mask(pac::Interrupt::DMA_IRQ_0);
+                cortex_m::asm::dmb();
+                fifo_write(MSG_STOPPED);
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
+// Runs rendering on core 1 while core 0 updates the game state. Each frame,
+// `submit` waits until core 1 has finished drawing the previous frame, copies
+// the state for it to draw from and lets it start. Core 1 owns the display
+// (and so the framebuffer) until `run` returns.
+//
+// The DMA interrupt that drives the scanout is moved to core 1 while it
+// renders. Other interrupts still run on core 0, so the render function
+// shouldn't use state shared with their handlers. Log messages from core 1
+// are dropped.
+pub struct RenderCore<'a, S> {
+    state: *mut S,
+    ready: bool,
+    _borrow: PhantomData<(&'a mut Display, &'a mut S)>,
+}
+
+impl<'a, S: Clone + Send + Sync> RenderCore<'a, S> {
+    // Starts core 1 and calls `func`, which drives the game loop and submits
+    // frames. `state` is the copy of the game state that core 1 draws from.
+    // Core 1 is stopped before returning, so the borrows can't outlive it.
+    pub fn run<R>(
+        display: &mut Display,
+        state: &mut S,
+        render: fn(&mut Display, &S),
+        func: impl FnOnce(&mut RenderCore<S>) -> R,
+    ) -> R {
+        let mut render_core = RenderCore::start(display, state, render);
+        func(&mut render_core)
+    }
+
+    fn start(display: &'a mut Display, state: &'a mut S, render: fn(&mut Display, &S)) -> Self {
+        unsafe {
+            // A pending scanout interrupt is picked up by core 1 once it
+            // unmasks it.
+            pac::NVIC::mask(pac::Interrupt::DMA_IRQ_0);
+            CORE1_TASK = Core1Task {
+                run: render_loop::<S>,
+                render: render as *const (),
+                display,
+                state: state as *const S as *const (),
+            };
+            launch_core1();
+        }
+        RenderCore {
+            state,
+            ready: false,
+            _borrow: PhantomData,
+        }
+    }
+
+    // Hands over a new frame to core 1, blocking while the previous one is
+    // still being drawn.
+    pub fn submit(&mut self, state: &S) {
+        self.wait_until_ready();
+        // Core 1 has finished reading the previous state.
+        cortex_m::asm::dmb();
+        unsafe {
+            (*self.state).clone_from(state);
+        }
+        // The new state must be written before core 1 sees the message.
+        cortex_m::asm::dmb();
+        self.ready = false;
+        fifo_write(MSG_FRAME);
+    }
+}
+
+impl<'a, S> RenderCore<'a, S> {
+    fn wait_until_ready(&mut self) {
+        while !self.ready {
+            self.ready = fifo_read() == MSG_READY;
+        }
+    }
+}
+
+// Waits for the current frame to be drawn and returns the display to core 0.
+impl<'a, S> Drop for RenderCore<'a, S> {
+    fn drop(&mut self) {
+        self.wait_until_ready();
+        fifo_write(MSG_STOP);
+        while fifo_read() != MSG_STOPPED {}
+        cortex_m::asm::dmb();
+        unsafe {
+            pac::NVIC::unmask(pac::Interrupt::DMA_IRQ_0);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use core::ops::Range;
+use embedded_graphics::pixelcolor::raw::RawU8;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+
+pub const PALETTE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct PaletteIndex(pub u8);
+
+impl PixelColor for PaletteIndex {
+    type Raw = RawU8;
+}
+
+impl From<RawU8> for PaletteIndex {
+    fn from(raw: RawU8) -> Self {
+        PaletteIndex(raw.into_inner())
+    }
+}
+
+#[derive(Clone)]
+pub struct Palette {
+    pub colors: [Rgb565; PALETTE_SIZE],
+}
+
+#[allow(clippy::new_without_default)]
+impl Palette {
+    pub fn new() -> Self {
+        Palette {
+            colors: [Rgb565::BLACK; PALETTE_SIZE],
+        }
+    }
+
+    pub fn from_colors(colors: &[Rgb565]) -> Self {
+        let mut palette = Palette::new();
+        palette.colors[0..colors.len()].copy_from_slice(colors);
+        palette
+    }
+
+    pub fn get(&self, index: PaletteIndex) -> Rgb565 {
+        self.colors[index.0 as usize]
+    }
+
+    pub fn set(&mut self, index: PaletteIndex, color: Rgb565) {
+        self.colors[index.0 as usize] = color;
+    }
+
+    // Rotates the entries in `range` by `amount` places. Positive amounts move
+    // colors towards higher indices.
+    pub fn cycle(&mut self, range: Range<usize>, amount: i32) {
+        let len = range.len() as i32;
+        if len == 0 {
+            return;
+        }
+        let amount = amount.rem_euclid(len) as usize;
+        self.colors[range].rotate_right(amount);
+    }
+
+    // Returns a palette that is `amount` of the way from this one to `other`,
+    // where 0 is this palette and 255 is `other`.
+    pub fn blend(&self, other: &Palette, amount: u8) -> Palette {
+        let mut result = Palette::new();
+        for ((dst, a), b) in result
+            .colors
+            .iter_mut()
+            .zip(self.colors.iter())
+            .zip(other.colors.iter())
+        {
+            *dst = lerp_color(*a, *b, amount);
+        }
+        result
+    }
+
+    // Returns a palette with every entry faded towards `color`.
+    pub fn fade_to(&self, color: Rgb565, amount: u8) -> Palette {
+        let mut result = self.clone();
+        for dst in result.colors.iter_mut() {
+            *dst = lerp_color(*dst, color, amount);
+        }
+        result
+    }
+}
+
+fn lerp(a: u8, b: u8, amount: u8) -> u8 {
+    (a as i32 + (b as i32 - a as i32) * amount as i32 / 255) as u8
+}
+
+pub fn lerp_color(a: Rgb565, b: Rgb565, amount: u8) -> Rgb565 {
+    Rgb565::new(
+        lerp(a.r(), b.r(), amount),
+        lerp(a.g(), b.g(), amount),
+        lerp(a.b(), b.b(), amount),
+    )
+}
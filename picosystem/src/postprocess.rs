@@ -0,0 +1,263 @@
+use crate::palette::lerp_color;
+use core::mem::discriminant;
+use embedded_graphics::pixelcolor::raw::RawU16;
+use embedded_graphics::pixelcolor::Rgb565;
+use embedded_graphics::prelude::*;
+
+pub const MAX_EFFECTS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    // Darkens every other row, where 255 makes them black.
+    Scanlines(u8),
+    // Reduces each channel to the given number of bits (green gets one more)
+    // with a 4x4 ordered dither.
+    Dither(u8),
+    // Multiplies by a color.
+    Tint(Rgb565),
+    Grayscale,
+    // Mixes with a color, where 0 leaves the image unchanged and 255 replaces
+    // it. Useful for transitions and hit flashes.
+    Fade(Rgb565, u8),
+}
+
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+impl Effect {
+    pub fn apply(self, color: Rgb565, p: Point) -> Rgb565 {
+        match self {
+            Effect::Scanlines(amount) => {
+                if p.y & 1 == 1 {
+                    lerp_color(color, Rgb565::BLACK, amount)
+                } else {
+                    color
+                }
+            }
+            Effect::Dither(bits) => {
+                let threshold = BAYER[(p.y & 3) as usize][(p.x & 3) as usize];
+                Rgb565::new(
+                    dither(color.r(), 5, bits, threshold),
+                    dither(color.g(), 6, bits.saturating_add(1), threshold),
+                    dither(color.b(), 5, bits, threshold),
+                )
+            }
+            Effect::Tint(tint) => Rgb565::new(
+                (color.r() as u16 * tint.r() as u16 / Rgb565::MAX_R as u16) as u8,
+                (color.g() as u16 * tint.g() as u16 / Rgb565::MAX_G as u16) as u8,
+                (color.b() as u16 * tint.b() as u16 / Rgb565::MAX_B as u16) as u8,
+            ),
+            Effect::Grayscale => {
+                // Luma in 6 bits, weighting red and blue as if they were 6 bits too.
+                let y = (color.r() as u32 * 2 * 77
+                    + color.g() as u32 * 150
+                    + color.b() as u32 * 2 * 29)
+                    / 256;
+                Rgb565::new((y / 2) as u8, y as u8, (y / 2) as u8)
+            }
+            Effect::Fade(target, amount) => lerp_color(color, target, amount),
+        }
+    }
+}
+
+fn dither(value: u8, depth: u8, bits: u8, threshold: u8) -> u8 {
+    if bits >= depth {
+        return value;
+    }
+    let max = (1u16 << depth) - 1;
+    let step = 1u16 << (depth - bits);
+    let value = (value as u16 + threshold as u16 * step / 16).min(max);
+    (value & !(step - 1)) as u8
+}
+
+// Effects applied in order to pixels as they are sent to the screen. At most
+// one effect of each kind is active.
+#[derive(Clone, Default, PartialEq)]
+pub struct PostProcess {
+    effects: heapless::Vec<Effect, MAX_EFFECTS>,
+}
+
+impl PostProcess {
+    pub const fn new() -> Self {
+        PostProcess {
+            effects: heapless::Vec::new(),
+        }
+    }
+
+    // Adds an effect after the existing ones, or updates the effect of the
+    // same kind in place.
+    pub fn set(&mut self, effect: Effect) {
+        match self
+            .effects
+            .iter_mut()
+            .find(|e| discriminant(*e) == discriminant(&effect))
+        {
+            Some(e) => *e = effect,
+            None => self.effects.push(effect).unwrap(),
+        }
+    }
+
+    // Removes the effect of the same kind as `effect`, ignoring its parameters.
+    pub fn remove(&mut self, effect: Effect) {
+        self.effects
+            .retain(|e| discriminant(e) != discriminant(&effect));
+    }
+
+    pub fn toggle(&mut self, effect: Effect, enabled: bool) {
+        if enabled {
+            self.set(effect);
+        } else {
+            self.remove(effect);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+
+    pub fn effects(&self) -> &[Effect] {
+        &self.effects
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    pub fn apply_color(&self, color: Rgb565, p: Point) -> Rgb565 {
+        self.effects
+            .iter()
+            .fold(color, |color, effect| effect.apply(color, p))
+    }
+
+    // Applies the effects to a big-endian pixel at `p` in the framebuffer.
+    // This is slow, use `CompiledPostProcess` for whole frames.
+    pub fn apply_be(&self, pixel: u16, p: Point) -> u16 {
+        let color = RawU16::new(u16::from_be(pixel)).into();
+        RawU16::from(self.apply_color(color, p))
+            .into_inner()
+            .to_be()
+    }
+}
+
+// Lookup tables for an effect that changes each channel on its own, indexed by
+// the 5 or 6-bit channel value.
+#[derive(Clone, Copy)]
+struct ChannelLut {
+    r: [u8; 32],
+    g: [u8; 64],
+    b: [u8; 32],
+}
+
+impl ChannelLut {
+    fn new(effect: Effect, p: Point) -> Self {
+        let mut lut = ChannelLut {
+            r: [0; 32],
+            g: [0; 64],
+            b: [0; 32],
+        };
+        for v in 0..64 {
+            let color = effect.apply(Rgb565::new(v.min(31), v, v.min(31)), p);
+            if v < 32 {
+                lut.r[v as usize] = color.r();
+                lut.b[v as usize] = color.b();
+            }
+            lut.g[v as usize] = color.g();
+        }
+        lut
+    }
+
+    // Returns the tables for applying `self` and then `next`.
+    fn then(&self, next: &ChannelLut) -> ChannelLut {
+        let mut lut = *self;
+        lut.r.iter_mut().for_each(|v| *v = next.r[*v as usize]);
+        lut.g.iter_mut().for_each(|v| *v = next.g[*v as usize]);
+        lut.b.iter_mut().for_each(|v| *v = next.b[*v as usize]);
+        lut
+    }
+
+    fn apply(&self, r: &mut u8, g: &mut u8, b: &mut u8) {
+        *r = self.r[*r as usize];
+        *g = self.g[*g as usize];
+        *b = self.b[*b as usize];
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Stage {
+    Lut(ChannelLut),
+    OddRowLut(ChannelLut),
+    Dither(u8),
+    Grayscale,
+}
+
+// Effects prepared for applying to every pixel sent to the screen, which has
+// to keep up with the display. Effects that change each channel on its own
+// become lookup tables, and consecutive ones are merged, so the rest only
+// needs shifts and adds.
+#[derive(Clone, Default)]
+pub struct CompiledPostProcess {
+    stages: heapless::Vec<Stage, MAX_EFFECTS>,
+}
+
+impl CompiledPostProcess {
+    pub const fn new() -> Self {
+        CompiledPostProcess {
+            stages: heapless::Vec::new(),
+        }
+    }
+
+    pub fn compile(post_process: &PostProcess) -> Self {
+        let mut stages = heapless::Vec::<Stage, MAX_EFFECTS>::new();
+        for &effect in post_process.effects() {
+            let stage = match effect {
+                Effect::Scanlines(_) => Stage::OddRowLut(ChannelLut::new(effect, Point::new(0, 1))),
+                Effect::Dither(bits) => Stage::Dither(bits),
+                Effect::Grayscale => Stage::Grayscale,
+                Effect::Tint(_) | Effect::Fade(..) => {
+                    let lut = ChannelLut::new(effect, Point::zero());
+                    if let Some(Stage::Lut(previous)) = stages.last_mut() {
+                        *previous = previous.then(&lut);
+                        continue;
+                    }
+                    Stage::Lut(lut)
+                }
+            };
+            let _ = stages.push(stage);
+        }
+        CompiledPostProcess { stages }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    // Same as `PostProcess::apply_be`.
+    pub fn apply_be(&self, pixel: u16, p: Point) -> u16 {
+        let pixel = u16::from_be(pixel);
+        let mut r = (pixel >> 11) as u8;
+        let mut g = ((pixel >> 5) & 0x3f) as u8;
+        let mut b = (pixel & 0x1f) as u8;
+        for stage in self.stages.iter() {
+            match stage {
+                Stage::Lut(lut) => lut.apply(&mut r, &mut g, &mut b),
+                Stage::OddRowLut(lut) => {
+                    if p.y & 1 == 1 {
+                        lut.apply(&mut r, &mut g, &mut b);
+                    }
+                }
+                Stage::Dither(bits) => {
+                    let threshold = BAYER[(p.y & 3) as usize][(p.x & 3) as usize];
+                    r = dither(r, 5, *bits, threshold);
+                    g = dither(g, 6, bits.saturating_add(1), threshold);
+                    b = dither(b, 5, *bits, threshold);
+                }
+                Stage::Grayscale => {
+                    let y = (r as u32 * 2 * 77 + g as u32 * 150 + b as u32 * 2 * 29) >> 8;
+                    r = (y >> 1) as u8;
+                    g = y as u8;
+                    b = (y >> 1) as u8;
+                }
+            }
+        }
+        (((r as u16) << 11) | ((g as u16) << 5) | b as u16).to_be()
+    }
+}
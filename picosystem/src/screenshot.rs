@@ -0,0 +1,67 @@
+// Sends the framebuffer to the host over the USB serial port. The frame is
+// interleaved with log output, so it starts with a magic number:
+//
+// magic: "PSCR"
+// width: u16 (little-endian)
+// height: u16 (little-endian)
+// format: u8 (0 = RGB565, big-endian)
+// reserved: u8
+// length: u32 (little-endian), number of pixel bytes
+// pixels: [u8; length], row-major
+// checksum: u32 (little-endian), wrapping sum of the pixel bytes
+//
+// Use `picosystem_screenshot` to convert it to a PNG.
+use crate::display::{framebuffer, indexed_framebuffer, Display, Mode, WIDTH};
+use crate::usb_logger;
+use embedded_graphics::prelude::Point;
+
+pub const MAGIC: [u8; 4] = *b"PSCR";
+pub const FORMAT_RGB565_BE: u8 = 0;
+
+pub fn send(display: &Display) {
+    if !usb_logger::connected() {
+        return;
+    }
+    log::info!("Sending screenshot");
+    let mode = display.mode();
+    let width = mode.width();
+    let height = mode.height();
+    let length = (width * height * 2) as u32;
+
+    let mut header = [0u8; 14];
+    header[0..4].copy_from_slice(&MAGIC);
+    header[4..6].copy_from_slice(&(width as u16).to_le_bytes());
+    header[6..8].copy_from_slice(&(height as u16).to_le_bytes());
+    header[8] = FORMAT_RGB565_BE;
+    header[10..14].copy_from_slice(&length.to_le_bytes());
+    usb_logger::write_all(&header);
+
+    let mut checksum: u32 = 0;
+    let mut row = [0u16; WIDTH];
+    for y in 0..height {
+        let row = &mut row[0..width];
+        if mode == Mode::Indexed {
+            let palette = display.palette_be();
+            let src = &indexed_framebuffer()[y * width..(y + 1) * width];
+            for (pixel, index) in row.iter_mut().zip(src.iter()) {
+                *pixel = palette[*index as usize];
+            }
+        } else {
+            row.copy_from_slice(&framebuffer()[y * width..(y + 1) * width]);
+        }
+        // Effects are applied while sending to the screen, not stored.
+        let post_process = display.post_process_ref();
+        if !post_process.is_empty() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = post_process.apply_be(*pixel, Point::new(x as i32, y as i32));
+            }
+        }
+        // Pixels are already stored big-endian.
+        let bytes = unsafe { core::slice::from_raw_parts(row.as_ptr() as *const u8, width * 2) };
+        for b in bytes {
+            checksum = checksum.wrapping_add(*b as u32);
+        }
+        usb_logger::write_all(bytes);
+    }
+    usb_logger::write_all(&checksum.to_le_bytes());
+}
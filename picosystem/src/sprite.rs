@@ -3,10 +3,352 @@ use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::Rectangle;
 
+// Clockwise rotation, of sprites and of the whole display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+// Applied when drawing: the sprite is flipped, then rotated clockwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub rotation: Rotation,
+}
+
+impl Orientation {
+    pub const IDENTITY: Orientation = Orientation {
+        flip_x: false,
+        flip_y: false,
+        rotation: Rotation::Rotate0,
+    };
+
+    pub fn flip_x() -> Self {
+        Orientation {
+            flip_x: true,
+            ..Orientation::IDENTITY
+        }
+    }
+
+    pub fn flip_y() -> Self {
+        Orientation {
+            flip_y: true,
+            ..Orientation::IDENTITY
+        }
+    }
+
+    pub fn rotate(rotation: Rotation) -> Self {
+        Orientation {
+            rotation,
+            ..Orientation::IDENTITY
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Sprite<'a> {
     pub size: Size,
     pub transparent_color: Option<u16>,
     pub data: &'a [u16],
+    pub orientation: Orientation,
+    // If set, `data` holds each row compressed with
+    // `picosystem_compressor::compress_skipping`, starting at these offsets.
+    // Transparent pixels are stored as skips.
+    pub compressed_rows: Option<&'a [u32]>,
+    // Opacity of uncompressed sprites with transparency, one bit per pixel
+    // starting from the least significant. Each row starts on a new word.
+    pub mask: Option<&'a [u32]>,
+    // 4-bit alpha of each pixel of uncompressed sprites, read with
+    // `pixel_alpha`. `Display::draw_sprite` blends with it; other targets
+    // draw pixels of at least half opacity as opaque.
+    pub alpha: Option<&'a [u8]>,
+}
+
+// Returns the alpha, from 0 to 15, of pixel `index` in packed 4-bit alpha
+// data. Each byte holds two pixels, the first in the low nibble.
+pub fn pixel_alpha(alpha: &[u8], index: usize) -> u8 {
+    (alpha[index / 2] >> ((index % 2) * 4)) & 0xf
+}
+
+// Iterates over the opaque runs of a mask row as column ranges.
+pub struct OpaqueRuns<'a> {
+    row: &'a [u32],
+    x: usize,
+    width: usize,
+}
+
+impl Iterator for OpaqueRuns<'_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        while self.x < self.width {
+            let bits = self.row[self.x / 32] >> (self.x % 32);
+            if bits != 0 {
+                self.x += bits.trailing_zeros() as usize;
+                break;
+            }
+            self.x = (self.x / 32 + 1) * 32;
+        }
+        if self.x >= self.width {
+            return None;
+        }
+        let start = self.x;
+        while self.x < self.width {
+            let ones = (self.row[self.x / 32] >> (self.x % 32)).trailing_ones() as usize;
+            self.x += ones;
+            if ones == 0 || self.x % 32 != 0 {
+                break;
+            }
+        }
+        self.x = self.x.min(self.width);
+        Some((start, self.x))
+    }
+}
+
+// A piece of a compressed row.
+enum Span<'a> {
+    Skip(usize),
+    Data(&'a [u16]),
+    Run(u16, usize),
+}
+
+impl Span<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Span::Skip(n) | Span::Run(_, n) => *n,
+            Span::Data(data) => data.len(),
+        }
+    }
+}
+
+struct RowSpans<'a> {
+    data: &'a [u16],
+    index: usize,
+    pending_run: Option<(u16, usize)>,
+}
+
+impl<'a> Iterator for RowSpans<'a> {
+    type Item = Span<'a>;
+
+    fn next(&mut self) -> Option<Span<'a>> {
+        if let Some((value, n)) = self.pending_run.take() {
+            return Some(Span::Run(value, n));
+        }
+        let ctrl = *self.data.get(self.index)?;
+        self.index += 1;
+        let data_length = (ctrl & 0xff) as usize;
+        let run_length = (ctrl >> 8) as usize;
+        if data_length == 0 {
+            return Some(Span::Skip(run_length));
+        }
+        let data = &self.data[self.index..self.index + data_length];
+        self.index += data_length;
+        if run_length > 0 {
+            self.pending_run = Some((data[data_length - 1], run_length));
+        }
+        Some(Span::Data(data))
+    }
+}
+
+impl<'a> Sprite<'a> {
+    pub fn with_orientation(&self, orientation: Orientation) -> Sprite<'a> {
+        Sprite {
+            orientation,
+            ..*self
+        }
+    }
+
+    pub fn mask_words_per_row(&self) -> usize {
+        (self.size.width as usize + 31) / 32
+    }
+
+    // Returns the opaque runs of row `y` of the stored image.
+    pub fn opaque_runs(&self, mask: &'a [u32], y: usize) -> OpaqueRuns<'a> {
+        let words = self.mask_words_per_row();
+        OpaqueRuns {
+            row: &mask[y * words..(y + 1) * words],
+            x: 0,
+            width: self.size.width as usize,
+        }
+    }
+
+    pub fn has_transparency(&self) -> bool {
+        self.mask.is_some() || self.alpha.is_some() || self.transparent_color.is_some()
+    }
+
+    // Whether the pixel at a point in oriented coordinates is drawn. The mask
+    // decides where there is one, so opaque pixels of the transparent color
+    // are drawn.
+    pub fn is_opaque(&self, p: Point) -> bool {
+        if let Some(mask) = self.mask {
+            let p = self.source_point(p);
+            let index = p.y as usize * self.mask_words_per_row() + p.x as usize / 32;
+            return mask[index] & (1 << (p.x % 32)) != 0;
+        }
+        if let (Some(alpha), None) = (self.alpha, self.compressed_rows) {
+            let p = self.source_point(p);
+            return pixel_alpha(alpha, (p.x + p.y * self.size.width as i32) as usize) >= 8;
+        }
+        match self.transparent_color {
+            Some(transparent_color) => self.pixel(p) != transparent_color,
+            None => true,
+        }
+    }
+
+    // Draws a sprite without orientation by copying each opaque run in bulk.
+    fn draw_masked<D>(&self, target: &mut D, mask: &[u32], area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let x0 = area.top_left.x as usize;
+        let x1 = x0 + area.size.width as usize;
+        let width = self.size.width as usize;
+        for (iy, y) in area.rows().enumerate() {
+            for (start, end) in self.opaque_runs(mask, y as usize) {
+                let start = start.max(x0);
+                let end = end.min(x1);
+                if start >= end {
+                    continue;
+                }
+                let row = y as usize * width;
+                target.fill_contiguous(
+                    &Rectangle::new(
+                        Point::new((start - x0) as i32, iy as i32),
+                        Size::new((end - start) as u32, 1),
+                    ),
+                    self.data[row + start..row + end]
+                        .iter()
+                        .map(|c| RawU16::new(*c).into()),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn row_spans(&self, rows: &[u32], y: usize) -> RowSpans<'a> {
+        let start = rows[y] as usize;
+        let end = rows.get(y + 1).map_or(self.data.len(), |&end| end as usize);
+        // Skip the decompressed size.
+        RowSpans {
+            data: &self.data[start + 1..end],
+            index: 0,
+            pending_run: None,
+        }
+    }
+
+    // Returns the stored value of a pixel in oriented coordinates. This is slow
+    // for compressed sprites, as the row is decoded up to the pixel.
+    fn pixel(&self, p: Point) -> u16 {
+        let p = self.source_point(p);
+        let rows = match self.compressed_rows {
+            Some(rows) => rows,
+            None => return self.data[(p.x + p.y * self.size.width as i32) as usize],
+        };
+        let mut x = 0;
+        for span in self.row_spans(rows, p.y as usize) {
+            let len = span.len();
+            if (p.x as usize) < x + len {
+                return match span {
+                    Span::Skip(_) => self.transparent_color.unwrap_or(0),
+                    Span::Data(data) => data[p.x as usize - x],
+                    Span::Run(value, _) => value,
+                };
+            }
+            x += len;
+        }
+        self.transparent_color.unwrap_or(0)
+    }
+
+    // Draws rows of a compressed sprite without orientation, a span at a time.
+    fn draw_compressed<D>(
+        &self,
+        target: &mut D,
+        rows: &[u32],
+        area: &Rectangle,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let x0 = area.top_left.x as usize;
+        let x1 = x0 + area.size.width as usize;
+        for (iy, y) in area.rows().enumerate() {
+            let mut x = 0;
+            for span in self.row_spans(rows, y as usize) {
+                let len = span.len();
+                let start = x.max(x0);
+                let end = (x + len).min(x1);
+                if start < end {
+                    let dst = Rectangle::new(
+                        Point::new((start - x0) as i32, iy as i32),
+                        Size::new((end - start) as u32, 1),
+                    );
+                    match span {
+                        Span::Skip(_) => {}
+                        Span::Data(data) => target.fill_contiguous(
+                            &dst,
+                            data[start - x..end - x]
+                                .iter()
+                                .map(|c| RawU16::new(*c).into()),
+                        )?,
+                        Span::Run(value, _) => {
+                            target.fill_solid(&dst, RawU16::new(value).into())?
+                        }
+                    }
+                }
+                x += len;
+                if x >= x1 {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the point in `data` shown at a point in oriented coordinates.
+    pub fn source_point(&self, p: Point) -> Point {
+        let w = self.size.width as i32;
+        let h = self.size.height as i32;
+        let p = match self.orientation.rotation {
+            Rotation::Rotate0 => p,
+            Rotation::Rotate90 => Point::new(p.y, h - 1 - p.x),
+            Rotation::Rotate180 => Point::new(w - 1 - p.x, h - 1 - p.y),
+            Rotation::Rotate270 => Point::new(w - 1 - p.y, p.x),
+        };
+        let x = if self.orientation.flip_x {
+            w - 1 - p.x
+        } else {
+            p.x
+        };
+        let y = if self.orientation.flip_y {
+            h - 1 - p.y
+        } else {
+            p.y
+        };
+        Point::new(x, y)
+    }
+
+    fn draw_oriented<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let offset = area.top_left;
+        if self.has_transparency() {
+            target.draw_iter(
+                area.points()
+                    .filter(|p| self.is_opaque(*p))
+                    .map(|p| Pixel(p - offset, RawU16::new(self.pixel(p)).into())),
+            )
+        } else {
+            target.fill_contiguous(
+                &Rectangle::new(Point::zero(), area.size),
+                area.points().map(|p| RawU16::new(self.pixel(p)).into()),
+            )
+        }
+    }
 }
 
 impl ImageDrawable for Sprite<'_> {
@@ -16,7 +358,13 @@ impl ImageDrawable for Sprite<'_> {
     where
         D: DrawTarget<Color = Self::Color>,
     {
-        if let Some(transparent_color) = self.transparent_color {
+        if self.orientation != Orientation::IDENTITY {
+            self.draw_oriented(target, &self.bounding_box())
+        } else if let Some(rows) = self.compressed_rows {
+            self.draw_compressed(target, rows, &self.bounding_box())
+        } else if let Some(mask) = self.mask {
+            self.draw_masked(target, mask, &self.bounding_box())
+        } else if let Some(transparent_color) = self.transparent_color {
             let mut x = 0;
             let mut y = 0;
             for p in self.data.iter() {
@@ -43,6 +391,15 @@ impl ImageDrawable for Sprite<'_> {
     where
         D: DrawTarget<Color = Self::Color>,
     {
+        if self.orientation != Orientation::IDENTITY {
+            return self.draw_oriented(target, area);
+        }
+        if let Some(rows) = self.compressed_rows {
+            return self.draw_compressed(target, rows, area);
+        }
+        if let Some(mask) = self.mask {
+            return self.draw_masked(target, mask, area);
+        }
         if let Some(transparent_color) = self.transparent_color {
             for (iy, y) in
                 (area.top_left.y..(area.top_left.y + area.size.height as i32)).enumerate()
@@ -77,7 +434,195 @@ impl ImageDrawable for Sprite<'_> {
 }
 
 impl OriginDimensions for Sprite<'_> {
+    fn size(&self) -> Size {
+        match self.orientation.rotation {
+            Rotation::Rotate90 | Rotation::Rotate270 => {
+                Size::new(self.size.height, self.size.width)
+            }
+            _ => self.size,
+        }
+    }
+}
+
+// A sprite stored as palette indices, one byte per pixel, so that it can be
+// drawn in other colors by substituting a palette of the same layout.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexedSprite<'a> {
+    pub size: Size,
+    pub data: &'a [u8],
+    // The colors the image was made with.
+    pub palette: &'a [Rgb565],
+    pub transparent_index: Option<u8>,
+}
+
+impl<'a> IndexedSprite<'a> {
+    // `palette` must have at least as many colors as the default palette.
+    pub fn with_palette<'b>(&self, palette: &'b [Rgb565]) -> IndexedSprite<'b>
+    where
+        'a: 'b,
+    {
+        assert!(
+            palette.len() >= self.palette.len(),
+            "palette has {} colors but the sprite uses {}",
+            palette.len(),
+            self.palette.len()
+        );
+        IndexedSprite {
+            size: self.size,
+            data: self.data,
+            palette,
+            transparent_index: self.transparent_index,
+        }
+    }
+
+    fn draw_area<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let width = self.size.width as usize;
+        let x0 = area.top_left.x as usize;
+        let x1 = x0 + area.size.width as usize;
+        let opaque = |i: &u8| Some(*i) != self.transparent_index;
+        for (iy, y) in area.rows().enumerate() {
+            let row = &self.data[y as usize * width..(y as usize + 1) * width];
+            let mut x = x0;
+            while x < x1 {
+                let start = x + row[x..x1].iter().position(opaque).unwrap_or(x1 - x);
+                let end = start
+                    + row[start..x1]
+                        .iter()
+                        .position(|i| !opaque(i))
+                        .unwrap_or(x1 - start);
+                if start < end {
+                    target.fill_contiguous(
+                        &Rectangle::new(
+                            Point::new((start - x0) as i32, iy as i32),
+                            Size::new((end - start) as u32, 1),
+                        ),
+                        row[start..end].iter().map(|i| self.palette[*i as usize]),
+                    )?;
+                }
+                x = end;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ImageDrawable for IndexedSprite<'_> {
+    type Color = Rgb565;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.draw_area(target, &self.bounding_box())
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.draw_area(target, area)
+    }
+}
+
+impl OriginDimensions for IndexedSprite<'_> {
     fn size(&self) -> Size {
         self.size
     }
 }
+
+// Scale factors are 16.16 fixed point.
+pub const SCALE_ONE: u32 = 1 << 16;
+
+// Draws a sprite scaled with nearest-neighbour sampling. Pixels that land
+// outside the target are skipped rather than passed to it.
+pub struct ScaledSprite<'a, 'b> {
+    sprite: &'a Sprite<'b>,
+    scale_x: u32,
+    scale_y: u32,
+}
+
+impl<'a, 'b> ScaledSprite<'a, 'b> {
+    pub fn new(sprite: &'a Sprite<'b>, scale: u32) -> Self {
+        Self::with_scales(sprite, scale, scale)
+    }
+
+    pub fn with_scales(sprite: &'a Sprite<'b>, scale_x: u32, scale_y: u32) -> Self {
+        assert!(scale_x > 0 && scale_y > 0);
+        ScaledSprite {
+            sprite,
+            scale_x,
+            scale_y,
+        }
+    }
+
+    pub fn integer(sprite: &'a Sprite<'b>, scale: u32) -> Self {
+        Self::new(sprite, scale * SCALE_ONE)
+    }
+
+    fn draw_area<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let offset = area.top_left;
+        let visible = target.bounding_box().translate(offset).intersection(area);
+        let sprite = self.sprite;
+        let size = sprite.size();
+        // Source pixels per destination pixel, in 16.16 fixed point. Rounded up
+        // so that whole multiples of the scale land exactly on source pixels.
+        let step = |scale: u32| ((1u64 << 32) + scale as u64 - 1) / scale as u64;
+        let step_x = step(self.scale_x);
+        let step_y = step(self.scale_y);
+        let source = |p: Point| {
+            let x = ((p.x as u64 * step_x) >> 16).min(size.width as u64 - 1);
+            let y = ((p.y as u64 * step_y) >> 16).min(size.height as u64 - 1);
+            Point::new(x as i32, y as i32)
+        };
+        if sprite.has_transparency() {
+            target.draw_iter(
+                visible
+                    .points()
+                    .map(|p| (p, source(p)))
+                    .filter(|(_, s)| sprite.is_opaque(*s))
+                    .map(|(p, s)| Pixel(p - offset, RawU16::new(sprite.pixel(s)).into())),
+            )
+        } else {
+            target.fill_contiguous(
+                &visible.translate(-offset),
+                visible
+                    .points()
+                    .map(|p| RawU16::new(sprite.pixel(source(p))).into()),
+            )
+        }
+    }
+}
+
+impl ImageDrawable for ScaledSprite<'_, '_> {
+    type Color = Rgb565;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.draw_area(target, &self.bounding_box())
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.draw_area(target, &area.intersection(&self.bounding_box()))
+    }
+}
+
+impl OriginDimensions for ScaledSprite<'_, '_> {
+    fn size(&self) -> Size {
+        let size = self.sprite.size();
+        Size::new(
+            ((size.width as u64 * self.scale_x as u64) >> 16) as u32,
+            ((size.height as u64 * self.scale_y as u64) >> 16) as u32,
+        )
+    }
+}
@@ -0,0 +1,193 @@
+use crate::audio::Audio;
+use crate::input::Input;
+use crate::time;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::renderer::TextRenderer;
+use embedded_graphics::text::Baseline;
+
+pub const MAX_LINES: usize = 64;
+
+const BLIP_US: u32 = 15_000;
+
+// Multi-line text wrapped to the width of `area`. The d-pad scrolls by a line
+// and A turns the page. With a typewriter effect, characters are revealed one
+// at a time up to the end of the visible page, and A reveals the rest of it.
+pub struct TextBox<'a, S> {
+    text: &'a str,
+    style: S,
+    area: Rectangle,
+    // Byte ranges of the wrapped lines.
+    lines: heapless::Vec<(usize, usize), MAX_LINES>,
+    first_line: usize,
+    revealed: usize,
+    reveal_interval_us: u32,
+    last_reveal_time: u32,
+    blip_freq: Option<u32>,
+    blip_start_time: Option<u32>,
+}
+
+impl<'a, S> TextBox<'a, S>
+where
+    S: TextRenderer,
+{
+    pub fn new(text: &'a str, style: S, area: Rectangle) -> Self {
+        let mut text_box = TextBox {
+            text,
+            style,
+            area,
+            lines: heapless::Vec::new(),
+            first_line: 0,
+            revealed: text.len(),
+            reveal_interval_us: 0,
+            last_reveal_time: 0,
+            blip_freq: None,
+            blip_start_time: None,
+        };
+        text_box.wrap();
+        text_box
+    }
+
+    // Reveals a character every `interval_us`, playing a short tone of
+    // `blip_freq` Hz for each one that isn't a space.
+    pub fn with_typewriter(mut self, interval_us: u32, blip_freq: Option<u32>) -> Self {
+        self.revealed = 0;
+        self.reveal_interval_us = interval_us;
+        self.last_reveal_time = time::time_us();
+        self.blip_freq = blip_freq;
+        self
+    }
+
+    fn measure(&self, start: usize, end: usize) -> u32 {
+        self.style
+            .measure_string(&self.text[start..end], Point::zero(), Baseline::Top)
+            .bounding_box
+            .size
+            .width
+    }
+
+    // Lines past `MAX_LINES` are dropped, and the text box finishes at the
+    // end of the last stored line.
+    fn push_line(&mut self, start: usize, end: usize) {
+        if self.lines.push((start, end)).is_err() {
+            log::info!("Text box has more than {} lines", MAX_LINES);
+        }
+    }
+
+    // Byte offset of the end of the last stored line.
+    fn text_end(&self) -> usize {
+        self.lines.last().map_or(0, |&(_, end)| end)
+    }
+
+    // Breaks lines at newlines and at the last space that fits. Words wider
+    // than the box are left to overflow.
+    fn wrap(&mut self) {
+        let width = self.area.size.width;
+        let mut offset = 0;
+        for paragraph in self.text.split('\n') {
+            let paragraph_end = offset + paragraph.len();
+            let mut start = offset;
+            let mut end = offset;
+            let mut pos = offset;
+            while pos <= paragraph_end {
+                let word_end = self.text[pos..paragraph_end]
+                    .find(' ')
+                    .map_or(paragraph_end, |i| pos + i);
+                if end > start && self.measure(start, word_end) > width {
+                    self.push_line(start, end);
+                    start = pos;
+                }
+                end = word_end;
+                pos = word_end + 1;
+            }
+            self.push_line(start, end);
+            offset = paragraph_end + 1;
+        }
+    }
+
+    pub fn visible_lines(&self) -> usize {
+        (self.area.size.height / self.style.line_height()).max(1) as usize
+    }
+
+    fn last_first_line(&self) -> usize {
+        self.lines.len().saturating_sub(self.visible_lines())
+    }
+
+    // Byte offset of the end of the last visible line.
+    fn page_end(&self) -> usize {
+        let last = (self.first_line + self.visible_lines()).min(self.lines.len());
+        self.lines[last - 1].1
+    }
+
+    pub fn scroll(&mut self, lines: i32) {
+        let first_line = self.first_line as i32 + lines;
+        self.first_line = first_line.clamp(0, self.last_first_line() as i32) as usize;
+    }
+
+    pub fn next_page(&mut self) {
+        self.scroll(self.visible_lines() as i32);
+    }
+
+    pub fn is_revealing(&self) -> bool {
+        self.revealed < self.page_end()
+    }
+
+    // True once the whole text has been revealed and the last page is shown.
+    pub fn is_finished(&self) -> bool {
+        self.revealed >= self.text_end() && self.first_line >= self.last_first_line()
+    }
+
+    pub fn update(&mut self, input: &mut Input, audio: &mut Audio) {
+        let now = time::time_us();
+        if let Some(start_time) = self.blip_start_time {
+            if now - start_time >= BLIP_US {
+                audio.stop();
+                self.blip_start_time = None;
+            }
+        }
+
+        if self.is_revealing() && now - self.last_reveal_time >= self.reveal_interval_us {
+            self.last_reveal_time = now;
+            let c = self.text[self.revealed..].chars().next().unwrap();
+            self.revealed += c.len_utf8();
+            if let (Some(freq), false) = (self.blip_freq, c.is_whitespace()) {
+                audio.start_tone(freq);
+                self.blip_start_time = Some(now);
+            }
+        }
+
+        if input.button_a.is_pressed() {
+            if self.is_revealing() {
+                self.revealed = self.page_end();
+            } else {
+                self.next_page();
+            }
+        }
+        if input.dpad_down.is_pressed() {
+            self.scroll(1);
+        }
+        if input.dpad_up.is_pressed() {
+            self.scroll(-1);
+        }
+    }
+
+    pub fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = S::Color>,
+    {
+        let line_height = self.style.line_height() as i32;
+        let visible_lines = self.lines[self.first_line..]
+            .iter()
+            .take(self.visible_lines());
+        for (i, &(start, end)) in visible_lines.enumerate() {
+            let end = end.min(self.revealed);
+            if end < start {
+                break;
+            }
+            let position = self.area.top_left + Point::new(0, i as i32 * line_height);
+            self.style
+                .draw_string(&self.text[start..end], position, Baseline::Top, target)?;
+        }
+        Ok(())
+    }
+}
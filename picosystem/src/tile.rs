@@ -5,6 +5,10 @@ pub const TILE_SIZE: i32 = 32;
 pub struct Tile {
     pub data: &'static [u16],
     pub mask: &'static [u32],
+    // Packed 4-bit alpha from `atlas!` in alpha mode, see
+    // `sprite::pixel_alpha`. Overlay pixels that are neither fully opaque nor
+    // fully transparent are left out of `mask` and blended with this.
+    pub alpha: Option<&'static [u8]>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -35,14 +39,17 @@ impl LoadedTile {
 
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 mod device {
-    use crate::display::{framebuffer, Display, HEIGHT, WIDTH};
+    use crate::display::{framebuffer, Display, Mode, HEIGHT, WIDTH};
     use crate::dma;
+    use crate::palette::lerp_color;
+    use crate::sprite::pixel_alpha;
     use crate::tile::*;
     use crate::time;
+    use embedded_graphics::pixelcolor::raw::RawU16;
     use embedded_graphics::prelude::*;
     use embedded_graphics::primitives::Rectangle;
 
-    fn load_tile(src: &Tile, dst: &mut LoadedTile, masked: bool) {
+    pub(crate) fn load_tile(src: &Tile, dst: &mut LoadedTile, masked: bool) {
         let mut buf = [0u16; (2 * TILE_SIZE * TILE_SIZE + 1) as usize];
         assert_eq!(src.data.len() % 2, 0);
         assert!(src.data.len() < buf.len());
@@ -206,6 +213,32 @@ mod device {
         clipped_dst.size == size
     }
 
+    fn blend_tile(display: &mut Display, alpha: &[u8], tile: &LoadedTile, dst: Point) {
+        let size = Size::new(TILE_SIZE as u32, TILE_SIZE as u32);
+        let clipped_dst = Rectangle::new(dst, size).intersection(&display.bounding_box());
+        let fb = framebuffer();
+        for p in clipped_dst.points() {
+            let src = p - dst;
+            let index = (src.x + src.y * TILE_SIZE) as usize;
+            let a = pixel_alpha(alpha, index);
+            if a == 0 || a == 15 {
+                continue;
+            }
+            let fb_index = p.x as usize + p.y as usize * WIDTH;
+            let background = RawU16::new(u16::from_be(fb[fb_index])).into();
+            let color = RawU16::new(u16::from_be(tile.data[index])).into();
+            let blended = lerp_color(background, color, a * 17);
+            fb[fb_index] = RawU16::from(blended).into_inner().to_be();
+        }
+    }
+
+    fn draw_overlay_tile(display: &mut Display, tile: &Tile, loaded_tile: &LoadedTile, dst: Point) {
+        draw_transparent_tile(display, loaded_tile, dst, Size::new(32, 32));
+        if let Some(alpha) = tile.alpha {
+            blend_tile(display, alpha, loaded_tile, dst);
+        }
+    }
+
     fn copy_tile(display: &mut Display, src: Point, dst: Point, size: Size) {
         let clipped_dst = Rectangle::new(dst, size).intersection(&display.bounding_box());
         let mut dma_channel = unsafe { dma::DmaChannel::new(dma::CHANNEL_TILE1) };
@@ -234,9 +267,12 @@ mod device {
     where
         F: Fn(Point) -> GenMapTile,
     {
+        assert_eq!(display.mode(), Mode::Hires);
         let subtile_mask = 32 - 1;
         let enable_tile_cache = true;
 
+        display.mark_all_dirty();
+
         let mut drawn_y: i32 = 0;
         let mut world_y = position.y;
         let subtile_y = position.y & subtile_mask;
@@ -257,9 +293,8 @@ mod device {
         let mut draw_time = 0;
         let mut load_time = 0;
         loop {
-            let progress = display.flush_progress();
-            let safe_y = (progress as i32 - WIDTH as i32 + 1) / WIDTH as i32;
-            if safe_y - drawn_y < 32 && progress < (WIDTH * HEIGHT) as usize {
+            let safe_y = display.safe_rows() as i32;
+            if safe_y - drawn_y < 32 && safe_y < HEIGHT as i32 {
                 continue;
             } else if safe_y - drawn_y > 64 {
                 slow_draw = true;
@@ -284,11 +319,11 @@ mod device {
                         if let Some(cached_overlay_tile) =
                             overlay_tile_cache.get(&tile_id(overlay_tile))
                         {
-                            draw_transparent_tile(
+                            draw_overlay_tile(
                                 display,
+                                overlay_tile,
                                 cached_overlay_tile,
                                 screen_coord,
-                                Size::new(32, 32),
                             );
                         } else {
                             overlay_tile_cache_misses += 1;
@@ -296,12 +331,7 @@ mod device {
                             let start_time = time::time_us();
                             load_tile(overlay_tile, &mut loaded_tile, true);
                             load_time += time::time_us() - start_time;
-                            draw_transparent_tile(
-                                display,
-                                &loaded_tile,
-                                screen_coord,
-                                Size::new(32, 32),
-                            );
+                            draw_overlay_tile(display, overlay_tile, &loaded_tile, screen_coord);
                             if overlay_tile_cache
                                 .insert(tile_id(overlay_tile), loaded_tile)
                                 .is_err()
@@ -345,19 +375,14 @@ mod device {
             for overlay_tile in map_tile.layers[1..].iter() {
                 overlay_tile_cache_lookups += 1;
                 if let Some(cached_overlay_tile) = overlay_tile_cache.get(&tile_id(overlay_tile)) {
-                    draw_transparent_tile(
-                        display,
-                        cached_overlay_tile,
-                        screen_coord,
-                        Size::new(32, 32),
-                    );
+                    draw_overlay_tile(display, overlay_tile, cached_overlay_tile, screen_coord);
                 } else {
                     overlay_tile_cache_misses += 1;
                     let mut loaded_tile = LoadedTile::new();
                     let start_time = time::time_us();
                     load_tile(overlay_tile, &mut loaded_tile, true);
                     load_time += time::time_us() - start_time;
-                    draw_transparent_tile(display, &loaded_tile, screen_coord, Size::new(32, 32));
+                    draw_overlay_tile(display, overlay_tile, &loaded_tile, screen_coord);
                     if overlay_tile_cache
                         .insert(tile_id(overlay_tile), loaded_tile)
                         .is_err()
@@ -395,3 +420,6 @@ mod device {
 
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub use device::draw;
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+pub(crate) use device::load_tile;
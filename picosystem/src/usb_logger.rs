@@ -1,7 +1,9 @@
 // Based on https://github.com/rp-rs/rp-hal/blob/c8bb2e43c792dd3975a255d7eba479547411aec6/boards/pico/examples/pico_usb_serial_interrupt.rs
+use crate::multicore;
 use crate::time;
 use core::fmt;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
 use log::LevelFilter;
 use log::{Level, Metadata, Record};
 use rp_pico::hal;
@@ -21,6 +23,13 @@ static mut USB_SERIAL: Option<SerialPort<hal::usb::UsbBus>> = None;
 
 static LOGGER: UsbSerialLogger = UsbSerialLogger;
 
+/// Set when the host sends the screenshot command.
+static SCREENSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+const SCREENSHOT_COMMAND: u8 = b'S';
+
+const WRITE_TIMEOUT_US: u32 = 1_000_000;
+
 pub fn init(
     regs: pac::USBCTRL_REGS,
     dpram: pac::USBCTRL_DPRAM,
@@ -86,6 +95,8 @@ unsafe fn USBCTRL_IRQ() {
                     if *b == 0 {
                         log::info!("Entering flash mode");
                         hal::rom_data::reset_to_usb_boot(0, 0);
+                    } else if *b == SCREENSHOT_COMMAND {
+                        SCREENSHOT_REQUESTED.store(true, Ordering::Relaxed);
                     }
                 });
             }
@@ -94,11 +105,48 @@ unsafe fn USBCTRL_IRQ() {
     }
 }
 
+pub fn take_screenshot_request() -> bool {
+    // The M0+ has no atomic swap, so keep the USB interrupt from setting the
+    // flag between the load and the store.
+    cortex_m::interrupt::free(|_| {
+        let requested = SCREENSHOT_REQUESTED.load(Ordering::Relaxed);
+        SCREENSHOT_REQUESTED.store(false, Ordering::Relaxed);
+        requested
+    })
+}
+
+// Writes all of `data` to the serial port, waiting for the host to read it.
+// Gives up if the host stops reading.
+pub fn write_all(mut data: &[u8]) {
+    let mut last_progress_time = time::time_us();
+    while !data.is_empty() {
+        pac::NVIC::mask(hal::pac::Interrupt::USBCTRL_IRQ);
+        let result = unsafe { USB_SERIAL.as_mut().unwrap().write(data) };
+        unsafe {
+            pac::NVIC::unmask(hal::pac::Interrupt::USBCTRL_IRQ);
+        }
+        match result {
+            Ok(count) => {
+                data = &data[count..];
+                last_progress_time = time::time_us();
+            }
+            Err(UsbError::WouldBlock) => {
+                if time::time_us() - last_progress_time > WRITE_TIMEOUT_US {
+                    return;
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
+
 struct UsbSerialLogger;
 
 impl log::Log for UsbSerialLogger {
+    // The USB interrupt and the serial port belong to core 0, so messages
+    // from core 1 are dropped.
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
+        metadata.level() <= Level::Info && multicore::core_id() == 0
     }
 
     fn log(&self, record: &Record) {
@@ -0,0 +1,138 @@
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+// Draws into a rectangular area of another target. Coordinates are relative to
+// the camera position, which is shown at the top left corner of the area, and
+// anything outside the area is clipped. The area may extend past the target.
+pub struct Viewport<'a, D> {
+    target: &'a mut D,
+    area: Rectangle,
+    // The part of `area` inside the target.
+    clip: Rectangle,
+    camera: Point,
+}
+
+impl<'a, D> Viewport<'a, D>
+where
+    D: DrawTarget,
+{
+    pub fn new(target: &'a mut D, area: Rectangle) -> Self {
+        let clip = area.intersection(&target.bounding_box());
+        Viewport {
+            target,
+            area,
+            clip,
+            camera: Point::zero(),
+        }
+    }
+
+    pub fn with_camera(mut self, camera: Point) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    pub fn set_camera(&mut self, camera: Point) {
+        self.camera = camera;
+    }
+
+    pub fn camera(&self) -> Point {
+        self.camera
+    }
+
+    pub fn area(&self) -> Rectangle {
+        self.area
+    }
+
+    pub fn to_screen(&self, p: Point) -> Point {
+        p - self.camera + self.area.top_left
+    }
+
+    pub fn to_local(&self, p: Point) -> Point {
+        p - self.area.top_left + self.camera
+    }
+}
+
+impl<'a, D> DrawTarget for Viewport<'a, D>
+where
+    D: DrawTarget,
+{
+    type Color = D::Color;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let offset = self.area.top_left - self.camera;
+        let clip = self.clip;
+        self.target.draw_iter(
+            pixels
+                .into_iter()
+                .map(|Pixel(p, color)| Pixel(p + offset, color))
+                .filter(|Pixel(p, _)| clip.contains(*p)),
+        )
+    }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let screen_area = area.translate(self.area.top_left - self.camera);
+        if self.clip.intersection(&screen_area) == screen_area {
+            self.target.fill_contiguous(&screen_area, colors)
+        } else {
+            self.draw_iter(area.points().zip(colors).map(|(p, color)| Pixel(p, color)))
+        }
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let screen_area = area.translate(self.area.top_left - self.camera);
+        self.target
+            .fill_solid(&self.clip.intersection(&screen_area), color)
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.target.fill_solid(&self.clip, color)
+    }
+}
+
+impl<'a, D> Dimensions for Viewport<'a, D> {
+    fn bounding_box(&self) -> Rectangle {
+        Rectangle::new(self.camera, self.area.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::mock_display::MockDisplay;
+    use embedded_graphics::pixelcolor::BinaryColor;
+
+    #[test]
+    fn test_negative_top_left() {
+        let mut display = MockDisplay::<BinaryColor>::new();
+        let area = Rectangle::new(Point::new(-2, -1), Size::new(5, 4));
+        let mut viewport = Viewport::new(&mut display, area);
+        assert_eq!(viewport.area(), area);
+        assert_eq!(viewport.to_screen(Point::new(3, 2)), Point::new(1, 1));
+        assert_eq!(viewport.to_local(Point::new(0, 0)), Point::new(2, 1));
+        assert_eq!(viewport.bounding_box().size, Size::new(5, 4));
+
+        Pixel(Point::new(0, 0), BinaryColor::On)
+            .draw(&mut viewport)
+            .unwrap();
+        Pixel(Point::new(3, 2), BinaryColor::On)
+            .draw(&mut viewport)
+            .unwrap();
+        viewport
+            .fill_solid(
+                &Rectangle::new(Point::new(4, 0), Size::new(2, 2)),
+                BinaryColor::Off,
+            )
+            .unwrap();
+        display.assert_pattern(&[
+            "  .", //
+            " # ", //
+        ]);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::{pack_alpha, quantize_alpha};
 use image::io::Reader as ImageReader;
 use image::GenericImageView;
 use proc_macro::TokenStream;
@@ -10,6 +11,7 @@ struct Atlas {
     function_name: Ident,
     path: LitStr,
     tile_size: LitInt,
+    alpha: bool,
 }
 
 impl Parse for Atlas {
@@ -19,10 +21,21 @@ impl Parse for Atlas {
         let path = input.parse()?;
         input.parse::<Token![,]>()?;
         let tile_size = input.parse()?;
+        let alpha = if input.is_empty() {
+            false
+        } else {
+            input.parse::<Token![,]>()?;
+            let option: Ident = input.parse()?;
+            if option != "alpha" {
+                return Err(syn::Error::new(option.span(), "expected `alpha`"));
+            }
+            true
+        };
         Ok(Atlas {
             function_name,
             path,
             tile_size,
+            alpha,
         })
     }
 }
@@ -32,6 +45,7 @@ pub fn atlas(input: TokenStream) -> TokenStream {
         function_name,
         path,
         tile_size,
+        alpha,
     } = parse_macro_input!(input as Atlas);
     let tile_size = tile_size.base10_parse::<u32>().unwrap();
     assert_eq!(tile_size as usize, TILE_SIZE);
@@ -49,14 +63,26 @@ pub fn atlas(input: TokenStream) -> TokenStream {
 
             let transparent_color = 0;
             let mut found_transparent_color = false;
-            let data: Vec<u16> = tile
+            // In alpha mode, partly transparent pixels keep their color but
+            // are left out of the mask, to be blended when drawn.
+            let alphas: Vec<u8> = tile
                 .pixels()
                 .map(|(_, _, p)| {
+                    if alpha {
+                        quantize_alpha(p[3])
+                    } else {
+                        p[3] / 255 * 15
+                    }
+                })
+                .collect();
+            let data: Vec<u16> = tile
+                .pixels()
+                .zip(alphas.iter())
+                .map(|((_, _, p), &a)| {
                     let r = p[0] as u16;
                     let g = p[1] as u16;
                     let b = p[2] as u16;
-                    let a = p[3] as u16;
-                    if a != 255 {
+                    if a == 0 {
                         found_transparent_color = true;
                         transparent_color
                     } else {
@@ -70,13 +96,29 @@ pub fn atlas(input: TokenStream) -> TokenStream {
                 let mut m: u32 = 0;
                 for x in 0..TILE_SIZE {
                     let color = data[(y * TILE_SIZE + x) as usize];
-                    if color != 0 {
+                    if color != 0 && alphas[y * TILE_SIZE + x] == 15 {
                         m |= 1 << x;
                     }
                 }
                 mask[y as usize] = m;
             }
 
+            let (alpha_code, alpha_field) = if alpha && alphas.iter().any(|&a| a != 0 && a != 15) {
+                let packed = pack_alpha(&alphas);
+                (
+                    format!(
+                        r#"
+            #[link_section = ".static_rodata"]
+            static ALPHA: [u8; {}] = {:?};"#,
+                        packed.len(),
+                        packed
+                    ),
+                    "Some(&ALPHA)",
+                )
+            } else {
+                (String::new(), "None")
+            };
+
             let mut compressed_data = [0u16; 2 * TILE_SIZE * TILE_SIZE + 1];
             let mut compressed_length =
                 picosystem_compressor::compress(&data, &mut compressed_data);
@@ -91,11 +133,12 @@ pub fn atlas(input: TokenStream) -> TokenStream {
             #[link_section = ".static_rodata"]
             static DATA: [u16; {}] = {:?};
             #[link_section = ".static_rodata"]
-            static MASK: [u32; {}] = {:?};
+            static MASK: [u32; {}] = {:?};{}
             #[link_section = ".static_rodata"]
             static TILE: picosystem::tile::Tile = picosystem::tile::Tile {{
                 data: &DATA,
                 mask: &MASK,
+                alpha: {},
             }};
             &TILE
         }}"#,
@@ -105,7 +148,9 @@ pub fn atlas(input: TokenStream) -> TokenStream {
                 compressed_length,
                 &compressed_data[0..compressed_length],
                 mask.len(),
-                &mask
+                &mask,
+                alpha_code,
+                alpha_field
             ));
 
             tile_index += 1;
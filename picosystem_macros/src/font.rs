@@ -0,0 +1,233 @@
+use image::io::Reader as ImageReader;
+use proc_macro::TokenStream;
+use syn::parse::{Parse, ParseStream, Result};
+use syn::{parse_macro_input, Ident, LitInt, LitStr, Token};
+
+// Glyph sheets are a grid of cells holding consecutive characters from here.
+const FIRST_SHEET_CHARACTER: u32 = 32;
+
+struct Font {
+    function_name: Ident,
+    path: LitStr,
+    // Only for glyph sheets.
+    cell_size: Option<(LitInt, LitInt)>,
+}
+
+impl Parse for Font {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let function_name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let path = input.parse()?;
+        let cell_size = if input.is_empty() {
+            None
+        } else {
+            input.parse::<Token![,]>()?;
+            let width = input.parse()?;
+            input.parse::<Token![,]>()?;
+            let height = input.parse()?;
+            Some((width, height))
+        };
+        Ok(Font {
+            function_name,
+            path,
+            cell_size,
+        })
+    }
+}
+
+struct Glyph {
+    character: char,
+    width: u32,
+    // Row-major, `width` pixels by the font height.
+    pixels: Vec<bool>,
+}
+
+struct LoadedFont {
+    height: u32,
+    baseline: u32,
+    letter_spacing: u32,
+    glyphs: Vec<Glyph>,
+}
+
+// Loads glyphs from a BDF file. Only characters up to U+00FF are kept.
+fn load_bdf(path: &str) -> LoadedFont {
+    let text = std::fs::read_to_string(path).expect(&format!("Could not load font {:?}", path));
+    let mut ascent = 0;
+    let mut descent = 0;
+    let mut glyphs = Vec::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap_or("");
+        let mut numbers = words.map(|w| w.parse::<i32>().unwrap_or(0));
+        match keyword {
+            "FONT_ASCENT" => ascent = numbers.next().unwrap(),
+            "FONT_DESCENT" => descent = numbers.next().unwrap(),
+            "STARTCHAR" => {
+                let height = (ascent + descent) as u32;
+                let mut encoding = -1;
+                let mut advance = 0;
+                let mut bbx = (0, 0, 0, 0);
+                for line in lines.by_ref() {
+                    let mut words = line.split_whitespace();
+                    let keyword = words.next().unwrap_or("");
+                    let mut numbers = words.map(|w| w.parse::<i32>().unwrap_or(0));
+                    match keyword {
+                        "ENCODING" => encoding = numbers.next().unwrap(),
+                        "DWIDTH" => advance = numbers.next().unwrap(),
+                        "BBX" => {
+                            let mut n = || numbers.next().unwrap();
+                            bbx = (n(), n(), n(), n());
+                        }
+                        "BITMAP" => break,
+                        _ => {}
+                    }
+                }
+                let (w, h, x_offset, y_offset) = bbx;
+                let width = advance.max(0) as u32;
+                let mut pixels = vec![false; (width * height) as usize];
+                for row in 0..h {
+                    let line = lines.next().unwrap();
+                    let bits = u64::from_str_radix(line.trim(), 16).unwrap();
+                    let bit_count = line.trim().len() as i32 * 4;
+                    let y = ascent - (y_offset + h) + row;
+                    for col in 0..w {
+                        let x = x_offset + col;
+                        let set = bits & (1u64 << (bit_count - 1 - col)) != 0;
+                        if set && (0..width as i32).contains(&x) && (0..height as i32).contains(&y)
+                        {
+                            pixels[(x + y * width as i32) as usize] = true;
+                        }
+                    }
+                }
+                if let Some(character) = char::from_u32(encoding as u32) {
+                    if (0..=0xff).contains(&encoding) {
+                        glyphs.push(Glyph {
+                            character,
+                            width,
+                            pixels,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    LoadedFont {
+        height: (ascent + descent) as u32,
+        baseline: ascent as u32,
+        letter_spacing: 0,
+        glyphs,
+    }
+}
+
+// Loads glyphs from an image with a grid of cells, starting with ' ' at the
+// top left. Opaque pixels are set. Glyphs are trimmed to their rightmost set
+// pixel, and empty cells other than ' ' are skipped.
+fn load_sheet(path: &str, cell_width: u32, cell_height: u32) -> LoadedFont {
+    let img = ImageReader::open(path)
+        .expect(&format!("Could not load image {:?}", path))
+        .decode()
+        .expect(&format!("Could not decode image {:?}", path))
+        .into_rgba8();
+    let columns = img.width() / cell_width;
+    let rows = img.height() / cell_height;
+    let mut glyphs = Vec::new();
+    for i in 0..columns * rows {
+        let character = char::from_u32(FIRST_SHEET_CHARACTER + i).unwrap();
+        let cell_x = (i % columns) * cell_width;
+        let cell_y = (i / columns) * cell_height;
+        let set = |x, y| img.get_pixel(cell_x + x, cell_y + y)[3] >= 128;
+        let width = (0..cell_width)
+            .filter(|&x| (0..cell_height).any(|y| set(x, y)))
+            .map(|x| x + 1)
+            .max();
+        let width = match (width, character) {
+            (Some(width), _) => width,
+            (None, ' ') => cell_width / 2,
+            (None, _) => continue,
+        };
+        let mut pixels = Vec::new();
+        for y in 0..cell_height {
+            for x in 0..width {
+                pixels.push(set(x, y));
+            }
+        }
+        glyphs.push(Glyph {
+            character,
+            width,
+            pixels,
+        });
+    }
+    LoadedFont {
+        height: cell_height,
+        // Assume the bottom fifth of the cell is for descenders.
+        baseline: cell_height - cell_height / 5,
+        letter_spacing: 1,
+        glyphs,
+    }
+}
+
+pub fn font(input: TokenStream) -> TokenStream {
+    let Font {
+        function_name,
+        path,
+        cell_size,
+    } = parse_macro_input!(input as Font);
+    let mut font = match cell_size {
+        Some((width, height)) => load_sheet(
+            &path.value(),
+            width.base10_parse().unwrap(),
+            height.base10_parse().unwrap(),
+        ),
+        None => load_bdf(&path.value()),
+    };
+    font.glyphs.sort_by_key(|g| g.character);
+
+    let mut bitmap: Vec<u8> = Vec::new();
+    let mut bit = 0;
+    let mut glyphs = String::new();
+    for glyph in font.glyphs.iter() {
+        glyphs.push_str(&format!(
+            "picosystem::font::Glyph {{ character: {:?}, width: {}, offset: {} }},",
+            glyph.character, glyph.width, bit
+        ));
+        for &set in glyph.pixels.iter() {
+            if bit % 8 == 0 {
+                bitmap.push(0);
+            }
+            if set {
+                *bitmap.last_mut().unwrap() |= 0x80 >> (bit % 8);
+            }
+            bit += 1;
+        }
+    }
+
+    let code = format!(
+        r#"
+        pub fn {}() -> &'static picosystem::font::Font<'static> {{
+            #[link_section = ".static_rodata"]
+            static BITMAP: [u8; {}] = {:?};
+            #[link_section = ".static_rodata"]
+            static GLYPHS: [picosystem::font::Glyph; {}] = [{}];
+            #[link_section = ".static_rodata"]
+            static FONT: picosystem::font::Font<'static> = picosystem::font::Font {{
+                height: {},
+                baseline: {},
+                letter_spacing: {},
+                glyphs: &GLYPHS,
+                bitmap: &BITMAP,
+            }};
+            &FONT
+        }}"#,
+        &function_name,
+        bitmap.len(),
+        &bitmap,
+        font.glyphs.len(),
+        glyphs,
+        font.height,
+        font.baseline,
+        font.letter_spacing,
+    );
+    code.parse().unwrap()
+}
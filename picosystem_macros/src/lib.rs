@@ -1,4 +1,5 @@
 mod atlas;
+mod font;
 mod map;
 
 use image::io::Reader as ImageReader;
@@ -10,6 +11,9 @@ struct Sprite {
     function_name: Ident,
     path: LitStr,
     width: LitInt,
+    compressed: bool,
+    alpha: bool,
+    indexed: bool,
 }
 
 impl Parse for Sprite {
@@ -19,20 +23,58 @@ impl Parse for Sprite {
         let path = input.parse()?;
         input.parse::<Token![,]>()?;
         let width = input.parse()?;
+        let mut compressed = false;
+        let mut alpha = false;
+        let mut indexed = false;
+        if !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            let option: Ident = input.parse()?;
+            if option == "compressed" {
+                compressed = true;
+            } else if option == "alpha" {
+                alpha = true;
+            } else if option == "indexed" {
+                indexed = true;
+            } else {
+                return Err(syn::Error::new(
+                    option.span(),
+                    "expected `compressed`, `alpha` or `indexed`",
+                ));
+            }
+        }
         Ok(Sprite {
             function_name,
             path,
             width,
+            compressed,
+            alpha,
+            indexed,
         })
     }
 }
 
+// Rounds an 8-bit alpha to 4 bits.
+fn quantize_alpha(a: u8) -> u8 {
+    ((a as u32 * 15 + 127) / 255) as u8
+}
+
+// Packs 4-bit alphas two to a byte, the first in the low nibble.
+fn pack_alpha(alphas: &[u8]) -> Vec<u8> {
+    alphas
+        .chunks(2)
+        .map(|pair| pair[0] | pair.get(1).map_or(0, |a| a << 4))
+        .collect()
+}
+
 #[proc_macro]
 pub fn sprite(input: TokenStream) -> TokenStream {
     let Sprite {
         function_name,
         path,
         width,
+        compressed,
+        alpha,
+        indexed,
     } = parse_macro_input!(input as Sprite);
     let width = width.base10_parse::<u32>().unwrap();
     let img = ImageReader::open(path.value())
@@ -41,17 +83,39 @@ pub fn sprite(input: TokenStream) -> TokenStream {
         .expect(&format!("Could not decode image {:?}", &path))
         .resize(width, 16384, image::imageops::FilterType::Triangle)
         .into_rgba8();
+    if indexed {
+        return indexed_sprite(&function_name, &img);
+    }
     let transparent_color = 0;
     let mut found_transparent_color = false;
-    let data: Vec<u16> = img
+    // Without alpha, any pixel that isn't fully opaque is transparent. With
+    // it, only pixels whose alpha rounds to 0 are, and drawing to something
+    // other than `Display` treats those under half opacity as transparent.
+    let alphas: Vec<u8> = img
         .pixels()
         .map(|p| {
+            if alpha {
+                quantize_alpha(p[3])
+            } else {
+                p[3] / 255 * 15
+            }
+        })
+        .collect();
+    let opaque: Vec<bool> = alphas
+        .iter()
+        .map(|&a| if alpha { a >= 8 } else { a == 15 })
+        .collect();
+    let data: Vec<u16> = img
+        .pixels()
+        .zip(alphas.iter())
+        .map(|(p, &a)| {
             let r = p[0] as u16;
             let g = p[1] as u16;
             let b = p[2] as u16;
-            let a = p[3] as u16;
-            if a != 255 {
+            if a != 15 {
                 found_transparent_color = true;
+            }
+            if a == 0 {
                 transparent_color
             } else {
                 ((r >> 3) << 11) | ((g >> 2) << 5) | ((b >> 3) << 0)
@@ -59,39 +123,198 @@ pub fn sprite(input: TokenStream) -> TokenStream {
         })
         .collect();
 
+    // Each row is compressed separately so that it can be decoded on its own.
+    // Only sprites with transparency store skips, so that opaque pixels of the
+    // transparent color are kept otherwise.
+    let (data, compressed_rows) = if compressed {
+        let mut compressed_data = Vec::new();
+        let mut rows = Vec::new();
+        let mut buffer = vec![0u16; 2 * img.width() as usize + 1];
+        for row in data.chunks(img.width() as usize) {
+            rows.push(compressed_data.len() as u32);
+            let length = if found_transparent_color {
+                picosystem_compressor::compress_skipping(row, transparent_color, &mut buffer)
+            } else {
+                picosystem_compressor::compress(row, &mut buffer)
+            };
+            compressed_data.extend_from_slice(&buffer[0..length]);
+        }
+        (compressed_data, Some(rows))
+    } else {
+        (data, None)
+    };
+
+    let (rows_code, rows_field) = match compressed_rows {
+        Some(rows) => (
+            format!(
+                r#"
+            #[link_section = ".static_rodata"]
+            static ROWS: [u32; {}] = {:?};"#,
+                rows.len(),
+                rows
+            ),
+            "Some(&ROWS)",
+        ),
+        None => (String::new(), "None"),
+    };
+
+    // Like tile masks, one bit per pixel with the leftmost pixel in the least
+    // significant bit, but each row takes as many words as its width needs.
+    let (mask_code, mask_field) = if found_transparent_color && !compressed {
+        let words_per_row = (img.width() as usize + 31) / 32;
+        let mut mask = Vec::new();
+        for row in opaque.chunks(img.width() as usize) {
+            let mut words = vec![0u32; words_per_row];
+            for (x, _) in row.iter().enumerate().filter(|(_, &o)| o) {
+                words[x / 32] |= 1 << (x % 32);
+            }
+            mask.extend_from_slice(&words);
+        }
+        (
+            format!(
+                r#"
+            #[link_section = ".static_rodata"]
+            static MASK: [u32; {}] = {:?};"#,
+                mask.len(),
+                mask
+            ),
+            "Some(&MASK)",
+        )
+    } else {
+        (String::new(), "None")
+    };
+
+    let (alpha_code, alpha_field) = if alpha && found_transparent_color {
+        let packed = pack_alpha(&alphas);
+        (
+            format!(
+                r#"
+            #[link_section = ".static_rodata"]
+            static ALPHA: [u8; {}] = {:?};"#,
+                packed.len(),
+                packed
+            ),
+            "Some(&ALPHA)",
+        )
+    } else {
+        (String::new(), "None")
+    };
+
     let mut code = String::new();
     code.push_str(&format!(
         r#"
         pub fn {}() -> &'static picosystem::sprite::Sprite<'static> {{
             #[link_section = ".static_rodata"]
-            static DATA: [u16; {}] = {:?};
+            static DATA: [u16; {}] = {:?};{}{}{}
             #[link_section = ".static_rodata"]
             static SPRITE: picosystem::sprite::Sprite<'static> = picosystem::sprite::Sprite {{
                 size: embedded_graphics::geometry::Size::new({}, {}),
                 transparent_color: {:?},
-                data: &DATA
+                data: &DATA,
+                orientation: picosystem::sprite::Orientation::IDENTITY,
+                compressed_rows: {},
+                mask: {},
+                alpha: {},
             }};
             &SPRITE
         }}"#,
         &function_name,
         data.len(),
         &data,
+        rows_code,
+        mask_code,
+        alpha_code,
         img.width(),
         img.height(),
         if found_transparent_color {
             Some(transparent_color)
         } else {
             None
-        }
+        },
+        rows_field,
+        mask_field,
+        alpha_field
     ));
     code.parse().unwrap()
 }
 
+// Emits an `IndexedSprite` with the colors of the image, in order of first
+// appearance, as its palette. Index 0 is transparent if the image has any
+// pixels that aren't fully opaque.
+fn indexed_sprite(function_name: &Ident, img: &image::RgbaImage) -> TokenStream {
+    let transparent = img.pixels().any(|p| p[3] != 255);
+    let mut palette: Vec<(u8, u8, u8)> = Vec::new();
+    if transparent {
+        palette.push((0, 0, 0));
+    }
+    let data: Vec<u8> = img
+        .pixels()
+        .map(|p| {
+            if p[3] != 255 {
+                return 0;
+            }
+            let color = (p[0] >> 3, p[1] >> 2, p[2] >> 3);
+            // Skip the placeholder for index 0 so opaque black gets its own.
+            let first = transparent as usize;
+            let index = match palette[first..].iter().position(|&c| c == color) {
+                Some(index) => first + index,
+                None => {
+                    palette.push(color);
+                    palette.len() - 1
+                }
+            };
+            assert!(index < 256, "Sprite has more than 256 colors");
+            index as u8
+        })
+        .collect();
+
+    let colors: String = palette
+        .iter()
+        .map(|(r, g, b)| {
+            format!(
+                "embedded_graphics::pixelcolor::Rgb565::new({}, {}, {}),",
+                r, g, b
+            )
+        })
+        .collect();
+    let code = format!(
+        r#"
+        pub fn {}() -> &'static picosystem::sprite::IndexedSprite<'static> {{
+            #[link_section = ".static_rodata"]
+            static DATA: [u8; {}] = {:?};
+            #[link_section = ".static_rodata"]
+            static PALETTE: [embedded_graphics::pixelcolor::Rgb565; {}] = [{}];
+            #[link_section = ".static_rodata"]
+            static SPRITE: picosystem::sprite::IndexedSprite<'static> = picosystem::sprite::IndexedSprite {{
+                size: embedded_graphics::geometry::Size::new({}, {}),
+                data: &DATA,
+                palette: &PALETTE,
+                transparent_index: {:?},
+            }};
+            &SPRITE
+        }}"#,
+        function_name,
+        data.len(),
+        &data,
+        palette.len(),
+        colors,
+        img.width(),
+        img.height(),
+        if transparent { Some(0) } else { None },
+    );
+    code.parse().unwrap()
+}
+
 #[proc_macro]
 pub fn atlas(input: TokenStream) -> TokenStream {
     atlas::atlas(input)
 }
 
+#[proc_macro]
+pub fn font(input: TokenStream) -> TokenStream {
+    font::font(input)
+}
+
 #[proc_macro]
 pub fn map(input: TokenStream) -> TokenStream {
     map::map(input)
@@ -0,0 +1,241 @@
+// Receives screenshots sent by `picosystem::screenshot` and saves them as PNG.
+//
+// Either connect to the device and request a screenshot:
+//   cargo run --target x86_64-unknown-linux-gnu -- --port /dev/ttyACM0
+// or extract all screenshots from a saved serial log:
+//   cargo run --target x86_64-unknown-linux-gnu -- --input log.bin
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use structopt::StructOpt;
+
+const MAGIC: &[u8; 4] = b"PSCR";
+const HEADER_LENGTH: usize = 14;
+const CHECKSUM_LENGTH: usize = 4;
+const FORMAT_RGB565_BE: u8 = 0;
+const SCREENSHOT_COMMAND: u8 = b'S';
+
+#[derive(StructOpt)]
+struct Opt {
+    /// Serial port of the PicoSystem.
+    #[structopt(long, parse(from_os_str), required_unless = "input")]
+    port: Option<PathBuf>,
+
+    /// File containing captured serial output.
+    #[structopt(long, parse(from_os_str), conflicts_with = "port")]
+    input: Option<PathBuf>,
+
+    /// Prefix of the PNG files to write.
+    #[structopt(long, default_value = "screenshot")]
+    output: String,
+}
+
+#[derive(Debug, PartialEq)]
+struct Frame {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Parse {
+    Frame(Frame, usize),
+    Incomplete,
+    Invalid,
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    u16::from_le_bytes([data[0], data[1]])
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+// Parses a frame starting at the beginning of `data`, returning the frame and
+// the number of bytes it used.
+fn parse_frame(data: &[u8]) -> Parse {
+    if data.len() < HEADER_LENGTH {
+        return Parse::Incomplete;
+    }
+    if &data[0..4] != MAGIC || data[8] != FORMAT_RGB565_BE {
+        return Parse::Invalid;
+    }
+    let width = read_u16(&data[4..]) as u32;
+    let height = read_u16(&data[6..]) as u32;
+    let length = read_u32(&data[10..]) as usize;
+    if length != (width * height * 2) as usize {
+        return Parse::Invalid;
+    }
+    let end = HEADER_LENGTH + length + CHECKSUM_LENGTH;
+    if data.len() < end {
+        return Parse::Incomplete;
+    }
+    let pixels = &data[HEADER_LENGTH..HEADER_LENGTH + length];
+    let checksum = pixels
+        .iter()
+        .fold(0u32, |sum, b| sum.wrapping_add(*b as u32));
+    if checksum != read_u32(&data[HEADER_LENGTH + length..]) {
+        return Parse::Invalid;
+    }
+    Parse::Frame(
+        Frame {
+            width,
+            height,
+            pixels: pixels.to_vec(),
+        },
+        end,
+    )
+}
+
+// Finds all frames in `data`, skipping log output between them. Returns the
+// frames and the number of bytes that have been fully processed.
+fn find_frames(data: &[u8]) -> (Vec<Frame>, usize) {
+    let mut frames = Vec::new();
+    let mut index = 0;
+    while index < data.len() {
+        if data[index] != MAGIC[0] {
+            index += 1;
+            continue;
+        }
+        match parse_frame(&data[index..]) {
+            Parse::Frame(frame, length) => {
+                frames.push(frame);
+                index += length;
+            }
+            Parse::Incomplete => break,
+            Parse::Invalid => index += 1,
+        }
+    }
+    (frames, index)
+}
+
+fn to_image(frame: &Frame) -> image::RgbImage {
+    image::RgbImage::from_fn(frame.width, frame.height, |x, y| {
+        let i = ((y * frame.width + x) * 2) as usize;
+        let color = u16::from_be_bytes([frame.pixels[i], frame.pixels[i + 1]]);
+        let r = ((color >> 11) & 0x1f) as u8;
+        let g = ((color >> 5) & 0x3f) as u8;
+        let b = (color & 0x1f) as u8;
+        image::Rgb([
+            (r << 3) | (r >> 2),
+            (g << 2) | (g >> 4),
+            (b << 3) | (b >> 2),
+        ])
+    })
+}
+
+fn save(frame: &Frame, output: &str, index: usize) {
+    let path = format!("{}-{}.png", output, index);
+    to_image(frame)
+        .save(&path)
+        .unwrap_or_else(|e| panic!("Could not save {:?}: {}", &path, e));
+    println!(
+        "Saved {}x{} screenshot to {}",
+        frame.width, frame.height, path
+    );
+}
+
+fn capture(port: &Path, output: &str) {
+    let mut port = serialport::new(port.to_string_lossy(), 115_200)
+        .timeout(Duration::from_secs(5))
+        .open()
+        .unwrap_or_else(|e| panic!("Could not open {:?}: {}", port, e));
+    port.write_all(&[SCREENSHOT_COMMAND])
+        .expect("Could not send screenshot command");
+
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let count = port
+            .read(&mut buf)
+            .expect("Could not read from serial port");
+        data.extend_from_slice(&buf[0..count]);
+        let (frames, _) = find_frames(&data);
+        if let Some(frame) = frames.first() {
+            save(frame, output, 0);
+            return;
+        }
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    if let Some(input) = &opt.input {
+        let data =
+            std::fs::read(input).unwrap_or_else(|e| panic!("Could not read {:?}: {}", input, e));
+        let (frames, _) = find_frames(&data);
+        if frames.is_empty() {
+            println!("No screenshots found");
+        }
+        for (i, frame) in frames.iter().enumerate() {
+            save(frame, &opt.output, i);
+        }
+    } else if let Some(port) = &opt.port {
+        capture(port, &opt.output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(width: u16, height: u16, pixels: &[u8]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.push(FORMAT_RGB565_BE);
+        data.push(0);
+        data.extend_from_slice(&(pixels.len() as u32).to_le_bytes());
+        data.extend_from_slice(pixels);
+        let checksum = pixels.iter().map(|b| *b as u32).sum::<u32>();
+        data.extend_from_slice(&checksum.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_find_frames_between_log_lines() {
+        let mut data = b"1.000 INFO - Sending screenshot\r\n".to_vec();
+        data.extend(encode(2, 1, &[0xf8, 0x00, 0x00, 0x1f]));
+        data.extend_from_slice(b"2.000 INFO - FPS: 60\r\n");
+        let (frames, _) = find_frames(&data);
+        assert_eq!(
+            frames,
+            vec![Frame {
+                width: 2,
+                height: 1,
+                pixels: vec![0xf8, 0x00, 0x00, 0x1f],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_incomplete_frame() {
+        let data = encode(2, 1, &[0xf8, 0x00, 0x00, 0x1f]);
+        let (frames, consumed) = find_frames(&data[0..data.len() - 1]);
+        assert!(frames.is_empty());
+        assert_eq!(consumed, 0);
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut data = encode(1, 1, &[0x12, 0x34]);
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert_eq!(parse_frame(&data), Parse::Invalid);
+    }
+
+    #[test]
+    fn test_to_image_big_endian() {
+        let frame = Frame {
+            width: 3,
+            height: 1,
+            pixels: vec![0xf8, 0x00, 0x07, 0xe0, 0x00, 0x1f],
+        };
+        let img = to_image(&frame);
+        assert_eq!(img.get_pixel(0, 0), &image::Rgb([255, 0, 0]));
+        assert_eq!(img.get_pixel(1, 0), &image::Rgb([0, 255, 0]));
+        assert_eq!(img.get_pixel(2, 0), &image::Rgb([0, 0, 255]));
+    }
+}